@@ -1,10 +1,27 @@
 use winit::event::WindowEvent;
 use winit::event_loop::EventLoop;
 use winit::window::Window;
-use egui::widgets::color_picker::{color_picker_color32, Alpha};
 use pixels::PixelsContext;
 use pixels::wgpu;
 
+use crate::image::Image;
+use crate::image_cropper::CropperEvent;
+use crate::page_strip::PageStrip;
+use crate::preview::Preview;
+use crate::palette::Palette;
+use crate::panel::{regions_panel, ColorTools, Settings};
+use crate::regions::{Region, Regions};
+
+/// Answer of the user when asked what to do with an incomplete region
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PromptAnswer {
+    /// Discard the region and close the window
+    Discard,
+
+    /// Go back to the window to finish it
+    KeepDrawing,
+}
+
 /// Manages all the state required to render egui over `Pixels`
 pub struct ColorPicker {
     /// egui and egui-winit primitives
@@ -17,17 +34,33 @@ pub struct ColorPicker {
     /// Changes on textures
     textures_delta: egui::TexturesDelta,
 
-    /// User data
-    pub color: egui::Color32,
+    /// egui renderer, created on the first render as it needs the device,
+    /// it keeps the textures uploaded so only their changes are sent
+    rpass: Option<egui_wgpu::renderer::RenderPass>,
+
+    /// Live preview of the crop of the selected region
+    preview: Preview,
 
-    /// Used to know if there is need to render ui elements
-    pub show: bool
+    /// Palettes, recent colors and eyedropper settings
+    pub colors: ColorTools,
+
+    /// Thumbnails of the pages open, shown when there are several
+    pub strip: PageStrip,
+
+    /// Ask what to do with the incomplete region before closing
+    pub prompt_incomplete: bool,
+
+    /// Answer to the incomplete region prompt, taken by the cropper
+    pub prompt_answer: Option<PromptAnswer>
 }
 
 impl ColorPicker {
-    /// Initialize egui
+    /// Initialize egui, `palettes` are offered to color the regions and
+    /// `strip` switches between the pages
     pub fn new(
-        event_loop: &EventLoop<()>
+        event_loop: &EventLoop<CropperEvent>,
+        palettes: Vec<Palette>,
+        strip: PageStrip
     ) -> Self {
         let context = egui::Context::default();
         let egui_state = egui_winit::State::new(event_loop);
@@ -37,11 +70,20 @@ impl ColorPicker {
             context,
             clipped_primitives: Vec::new(),
             textures_delta: egui::TexturesDelta::default(),
-            color: egui::Color32::GRAY,
-            show: false
+            rpass: None,
+            preview: Preview::default(),
+            colors: ColorTools::new(palettes),
+            strip,
+            prompt_incomplete: false,
+            prompt_answer: None
         }
     }
 
+    /// Forget the preview, the image it was sampled from changed
+    pub fn image_changed(&mut self) {
+        self.preview = Preview::default();
+    }
+
     /// Handle a event and return if it is exclusive to egui or should be
     /// processed by underlying elements
     pub fn handle_event(&mut self, event: &WindowEvent) -> bool {
@@ -51,23 +93,31 @@ impl ColorPicker {
     /// Prepare the egui primitives for rendering, process all the received
     /// input till this funcion is called and update all the commands that
     /// will be sent at `self.render()`
-    pub fn prepare(&mut self, window: &Window) {
-        let mut color = self.color.clone();
-
+    ///
+    /// The edits made on the side panel are applied on `regions` and
+    /// `settings`, and the crop of the selected region of `image` is
+    /// previewed
+    pub fn prepare(
+        &mut self,
+        window: &Window,
+        image: &Image,
+        regions: &mut Regions,
+        settings: &mut Settings
+    ) {
         // Extract (and clear) the egui captured raw input
-        let raw_input = self.egui_state.take_egui_input(&window);
+        let raw_input = self.egui_state.take_egui_input(window);
 
         // Process that input and create all the paint jobs required to draw a
         // new frame, also the changes issued by us for exaple the color pick
-        let output = self.context.run(raw_input, |egui_ctx| {
-            color = self.ui(egui_ctx);
+        let context = self.context.clone();
+        let output = context.run(raw_input, |egui_ctx| {
+            self.ui(egui_ctx, image, regions, settings);
         });
-        self.color = color;
 
         // Do any external output issued from winit like for example updating
         // the cursor, copy text to clipboard, open URL, etc ...
         self.egui_state.handle_platform_output(
-            &window,
+            window,
             &self.context,
             output.platform_output
         );
@@ -82,35 +132,93 @@ impl ColorPicker {
         );
     }
 
-    /// Egui ui elements to render, produces no elements if there is no need
-    /// to render
-    ///
-    /// Returns the user input
-    fn ui(&self, ctx: &egui::Context) -> egui::Color32 {
-        // Check if there really is need for UI
-        if !self.show {
-            return self.color.clone();
+    /// Egui ui elements to render, the user input is applied on `regions`
+    /// and `settings`
+    fn ui(
+        &mut self,
+        ctx: &egui::Context,
+        image: &Image,
+        regions: &mut Regions,
+        settings: &mut Settings
+    ) {
+        if self.prompt_incomplete {
+            self.incomplete_prompt(ctx);
+        }
+
+        // Before the side panels so it spans the whole window
+        if self.strip.len() > 1 {
+            self.strip.show(ctx, regions.len());
         }
 
-        // Render the color picker, and return the picked color
-        let mut color = self.color.clone();
-        egui::Window::new("My window")
-            // .frame(egui::Frame::none().fill(egui::Color32::TRANSPARENT))
-            .show(ctx, |ui| 
+        regions_panel(
+            ctx,
+            regions,
+            settings,
+            &mut self.colors,
+            image
+        );
+
+        if let Some(region) = regions.selected() {
+            self.preview_panel(ctx, image, region);
+        }
+    }
+
+    /// Side panel with the crop of `region` as it will be exported
+    fn preview_panel(
+        &mut self,
+        ctx: &egui::Context,
+        image: &Image,
+        region: &Region
+    ) {
+        let preview = &mut self.preview;
+        egui::SidePanel::right("preview").show(ctx, |ui| {
+            ui.heading(&region.name);
+            match preview.update(ctx, image, region) {
+                Some(texture) => {
+                    // Fit the preview on the panel width, over white paper
+                    let [width, height] = texture.size_vec2().into();
+                    let scale = (ui.available_width() / width).min(1.0);
+                    ui.add(egui::Image::new(
+                            texture.id(),
+                            egui::vec2(width * scale, height * scale))
+                        .bg_fill(egui::Color32::WHITE));
+                }
+                None => {
+                    ui.label("The region is empty");
+                }
+            }
+        });
+    }
+
+    /// Ask what to do with the region that is being drawn
+    fn incomplete_prompt(&mut self, ctx: &egui::Context) {
+        let mut answer = None;
+        egui::Window::new("Incomplete region")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+            .show(ctx, |ui|
         {
-            color_picker_color32(
-                ui,
-                &mut color,
-                Alpha::Opaque
-            );
+            ui.label("The last region is still being drawn");
+            ui.horizontal(|ui| {
+                if ui.button("Discard it and close").clicked() {
+                    answer = Some(PromptAnswer::Discard);
+                }
+                if ui.button("Keep drawing").clicked() {
+                    answer = Some(PromptAnswer::KeepDrawing);
+                }
+            });
         });
 
-        color
+        if answer.is_some() {
+            self.prompt_incomplete = false;
+            self.prompt_answer = answer;
+        }
     }
 
     /// Render the egui elements on the render target
     pub fn render(
-        &self,
+        &mut self,
         size: [u32; 2],
         encoder: &mut wgpu::CommandEncoder,
         render_target: &wgpu::TextureView,
@@ -121,11 +229,13 @@ impl ColorPicker {
         }: &PixelsContext
     ) {
         // Create egui renderer
-        let mut rpass = egui_wgpu::renderer::RenderPass::new(
-            device,
-            wgpu::TextureFormat::Bgra8UnormSrgb,
-            1
-        );
+        let rpass = self.rpass.get_or_insert_with(|| {
+            egui_wgpu::renderer::RenderPass::new(
+                device,
+                wgpu::TextureFormat::Bgra8UnormSrgb,
+                1
+            )
+        });
 
         // Create a descriptor of the screen, that will tell the renderer how
         // to render its elements (scale, position) on the texture
@@ -166,5 +276,8 @@ impl ColorPicker {
         for id in &self.textures_delta.free {
             rpass.free_texture(id);
         }
+
+        // The render pass keeps the textures, so the changes are applied
+        self.textures_delta.clear();
     }
 }
@@ -0,0 +1,528 @@
+use std::path::Path;
+
+use image::{Rgba, RgbaImage};
+use image::imageops::{self, FilterType};
+use miniz_oxide::deflate::compress_to_vec_zlib;
+use pdf_writer::{Content, Filter, Finish, Name, Pdf, Rect, Ref, Str};
+
+use crate::error::{Error, Result};
+use crate::image::Image;
+use crate::text;
+
+const CAPTION_COLOR: Rgba<u8> = Rgba([40, 40, 40, 255]);
+
+/// How the crops are arranged on the sheet
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Layout {
+    /// Equally sized cells, `columns` per row, crops are scaled down to fit
+    /// their cell
+    Grid { columns: u32 },
+
+    /// Crops keep their size and are placed left to right, wrapping to a new
+    /// row when they don't fit
+    Flow,
+}
+
+/// Lays out a set of crops on a single sheet or across several pages, with
+/// the name of every crop as its caption
+#[derive(Debug, Clone)]
+pub struct ContactSheet {
+    pub layout: Layout,
+
+    /// Width of the sheet in pixels
+    pub width: u32,
+
+    /// Height of every page in pixels, when `None` a single page that grows
+    /// to fit every crop is produced
+    pub page_height: Option<u32>,
+
+    /// Space between the border of the sheet and the crops
+    pub margin: u32,
+
+    /// Space between crops
+    pub spacing: u32,
+
+    /// Height of the captions in pixels, `0.0` disables them
+    pub caption_size: f32,
+
+    /// Density of the sheet, crops with a known density keep their physical
+    /// size and PDF pages are sized from it
+    pub dpi: f32,
+
+    pub background: Rgba<u8>,
+}
+
+impl Default for ContactSheet {
+    /// A4 wide sheet at 150 DPI with a grid of 3 columns
+    fn default() -> Self {
+        Self {
+            layout: Layout::Grid { columns: 3 },
+            width: 1240,
+            page_height: None,
+            margin: 60,
+            spacing: 30,
+            caption_size: 24.0,
+            dpi: 150.0,
+            background: Rgba([255, 255, 255, 255]),
+        }
+    }
+}
+
+/// Where a crop is placed on a page
+struct Placement {
+    crop: usize,
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+/// Placed crops of a page
+struct Page {
+    placements: Vec<Placement>,
+    height: u32,
+}
+
+impl ContactSheet {
+    /// A4 pages at 150 DPI
+    pub fn a4_pages() -> Self {
+        Self {
+            page_height: Some(1754),
+            ..Self::default()
+        }
+    }
+
+    fn caption_height(&self) -> u32 {
+        if self.caption_size > 0.0 {
+            (self.caption_size * 1.5).ceil() as u32
+        } else {
+            0
+        }
+    }
+
+    /// Size of a crop on the sheet, scaled down to fit in `max_width` and in
+    /// a page
+    fn fit(&self, crop: &Image, max_width: u32) -> (u32, u32) {
+        let (width, height) = crop.dimensions();
+        let (mut width, mut height) = match crop.dpi() {
+            Some((dpi_x, dpi_y)) => (
+                width as f32 * self.dpi / dpi_x,
+                height as f32 * self.dpi / dpi_y
+            ),
+            None => (width as f32, height as f32),
+        };
+
+        let max_height = self.page_height
+            .map(|page_height| page_height
+                .saturating_sub(2 * self.margin + self.caption_height()))
+            .unwrap_or(u32::MAX)
+            .max(1);
+        let scale = (max_width as f32 / width)
+            .min(max_height as f32 / height)
+            .min(1.0);
+        width *= scale;
+        height *= scale;
+
+        ((width.round() as u32).max(1), (height.round() as u32).max(1))
+    }
+
+    /// Split the crops into rows, the placements are relative to the top of
+    /// their row
+    fn rows(&self, crops: &[Image]) -> Vec<Vec<Placement>> {
+        let content_width = self.width.saturating_sub(2 * self.margin).max(1);
+        let mut rows: Vec<Vec<Placement>> = Vec::new();
+        let mut row = Vec::new();
+        let mut x = self.margin;
+
+        for (idx, crop) in crops.iter().enumerate() {
+            let placement = match self.layout {
+                Layout::Grid { columns } => {
+                    let columns = columns.max(1);
+                    let cell_width = content_width
+                        .saturating_sub((columns - 1) * self.spacing)
+                        / columns;
+                    let (width, height) = self.fit(crop, cell_width.max(1));
+                    if row.len() as u32 == columns {
+                        rows.push(std::mem::take(&mut row));
+                    }
+                    let column = row.len() as u32;
+
+                    Placement {
+                        crop: idx,
+                        x: self.margin + column * (cell_width + self.spacing),
+                        y: 0,
+                        width,
+                        height
+                    }
+                }
+                Layout::Flow => {
+                    let (width, height) = self.fit(crop, content_width);
+                    if !row.is_empty()
+                            && x + width > self.margin + content_width {
+                        rows.push(std::mem::take(&mut row));
+                        x = self.margin;
+                    }
+                    let placement = Placement {
+                        crop: idx,
+                        x,
+                        y: 0,
+                        width,
+                        height
+                    };
+                    x += width + self.spacing;
+
+                    placement
+                }
+            };
+            row.push(placement);
+        }
+        if !row.is_empty() {
+            rows.push(row);
+        }
+
+        rows
+    }
+
+    /// Place the rows on pages
+    fn pages(&self, crops: &[Image]) -> Vec<Page> {
+        let mut pages = Vec::new();
+        let mut placements: Vec<Placement> = Vec::new();
+        let mut y = self.margin;
+
+        for row in self.rows(crops) {
+            let row_height = row.iter()
+                .map(|placement| placement.height)
+                .max()
+                .unwrap_or(0) + self.caption_height();
+
+            // Start a new page if the row doesn't fit on the current one
+            if let Some(page_height) = self.page_height {
+                if !placements.is_empty()
+                        && y + row_height + self.margin > page_height {
+                    pages.push(Page {
+                        placements: std::mem::take(&mut placements),
+                        height: page_height
+                    });
+                    y = self.margin;
+                }
+            }
+
+            for mut placement in row {
+                placement.y = y;
+                placements.push(placement);
+            }
+            y += row_height + self.spacing;
+        }
+
+        let height = self.page_height
+            .unwrap_or((y + self.margin).saturating_sub(self.spacing));
+        pages.push(Page { placements, height });
+
+        pages
+    }
+
+    /// Caption of a crop, shortened to fit on `width` with `ellipsis` at the
+    /// end
+    fn caption(&self, crop: &Image, width: u32, ellipsis: &str) -> String {
+        let fits = |caption: &str| {
+            text::measure(caption, self.caption_size).0 <= width as f32
+        };
+
+        let mut caption = crop.name().to_owned();
+        if fits(&caption) {
+            return caption;
+        }
+        while caption.pop().is_some() {
+            let shortened = format!("{}{}", caption, ellipsis);
+            if fits(&shortened) {
+                return shortened;
+            }
+        }
+
+        caption
+    }
+
+    /// Render every page of the sheet
+    pub fn render(&self, crops: &[Image]) -> Vec<RgbaImage> {
+        self.pages(crops).iter().map(|page| {
+            let mut sheet = RgbaImage::from_pixel(
+                self.width, page.height, self.background);
+            for placement in &page.placements {
+                let crop = &crops[placement.crop];
+                let resized = imageops::resize(
+                    crop.buffer(),
+                    placement.width, placement.height,
+                    FilterType::Triangle
+                );
+                imageops::overlay(
+                    &mut sheet,
+                    &resized,
+                    placement.x as i64, placement.y as i64
+                );
+
+                if self.caption_size > 0.0 {
+                    text::draw(
+                        &mut sheet,
+                        &self.caption(crop, placement.width, "…"),
+                        placement.x as f32,
+                        (placement.y + placement.height) as f32
+                            + self.caption_size * 0.25,
+                        self.caption_size,
+                        CAPTION_COLOR
+                    );
+                }
+            }
+
+            sheet
+        }).collect()
+    }
+
+    /// Save the sheet, as a multi-page PDF if `path` has the `pdf` extension
+    /// or as images otherwise, when there are several pages every page is
+    /// saved with its number appended to the file name
+    pub fn save(&self, crops: &[Image], path: &Path) -> Result<()> {
+        if path.extension().and_then(|ext| ext.to_str()) == Some("pdf") {
+            return self.save_pdf(crops, path);
+        }
+
+        let pages = self.render(crops);
+        if pages.len() == 1 {
+            pages[0].save(path)?;
+        } else {
+            let stem = path.file_stem()
+                .and_then(|stem| stem.to_str())
+                .ok_or_else(|| Error::InvalidFileName(path.to_owned()))?;
+            let extension = path.extension()
+                .and_then(|ext| ext.to_str())
+                .unwrap_or("png");
+            for (idx, page) in pages.iter().enumerate() {
+                page.save(path.with_file_name(
+                    format!("{}-{}.{}", stem, idx + 1, extension)))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Save the sheet as a PDF, the crops are embedded at their full
+    /// resolution and the captions are written as text
+    pub fn save_pdf(&self, crops: &[Image], path: &Path) -> Result<()> {
+        let pages = self.pages(crops);
+
+        // Points per pixel of the sheet
+        let k = 72.0 / self.dpi;
+
+        let mut pdf = Pdf::new();
+        let mut next_id = 1;
+        let mut alloc = || {
+            next_id += 1;
+            Ref::new(next_id - 1)
+        };
+        let catalog_id = alloc();
+        let page_tree_id = alloc();
+        let font_id = alloc();
+        let font_name = Name(b"F1");
+        let page_ids: Vec<Ref> = pages.iter().map(|_| alloc()).collect();
+
+        pdf.catalog(catalog_id).pages(page_tree_id);
+        pdf.pages(page_tree_id)
+            .kids(page_ids.iter().copied())
+            .count(page_ids.len() as i32);
+        pdf.type1_font(font_id).base_font(Name(b"Helvetica"));
+
+        for (page, page_id) in pages.iter().zip(&page_ids) {
+            let content_id = alloc();
+            let page_height = page.height as f32 * k;
+            let mut content = Content::new();
+            let mut x_objects = Vec::new();
+
+            for placement in &page.placements {
+                let crop = &crops[placement.crop];
+                let image_name = format!("Im{}", placement.crop);
+                let image_id = alloc();
+                let s_mask_id = alloc();
+                write_image(&mut pdf, crop, image_id, s_mask_id);
+
+                let x = placement.x as f32 * k;
+                let width = placement.width as f32 * k;
+                let height = placement.height as f32 * k;
+                let bottom = page_height
+                    - (placement.y + placement.height) as f32 * k;
+                content.save_state();
+                content.transform([width, 0.0, 0.0, height, x, bottom]);
+                content.x_object(Name(image_name.as_bytes()));
+                content.restore_state();
+
+                if self.caption_size > 0.0 {
+                    // Helvetica only covers latin characters, so it's shortened
+                    // with dots instead of an ellipsis
+                    let caption: String = self
+                        .caption(crop, placement.width, "...")
+                        .chars()
+                        .map(|c| if c.is_ascii() { c } else { '.' })
+                        .collect();
+                    content.set_fill_gray(0.16);
+                    content.begin_text();
+                    content.set_font(font_name, self.caption_size * 0.8 * k);
+                    content.next_line(x, bottom - self.caption_size * k);
+                    content.show(Str(caption.as_bytes()));
+                    content.end_text();
+                }
+
+                x_objects.push((image_name, image_id));
+            }
+
+            let mut pdf_page = pdf.page(*page_id);
+            pdf_page.media_box(Rect::new(
+                0.0, 0.0, self.width as f32 * k, page_height));
+            pdf_page.parent(page_tree_id);
+            pdf_page.contents(content_id);
+            let mut resources = pdf_page.resources();
+            resources.fonts().pair(font_name, font_id);
+            let mut resource_objects = resources.x_objects();
+            for (image_name, image_id) in &x_objects {
+                resource_objects.pair(Name(image_name.as_bytes()), *image_id);
+            }
+            resource_objects.finish();
+            resources.finish();
+            pdf_page.finish();
+
+            pdf.stream(content_id, &content.finish());
+        }
+
+        std::fs::write(path, pdf.finish())?;
+
+        Ok(())
+    }
+}
+
+/// Embed `crop` on the pdf, with its alpha channel as soft mask
+fn write_image(pdf: &mut Pdf, crop: &Image, image_id: Ref, s_mask_id: Ref) {
+    const LEVEL: u8 = 6;
+
+    let (width, height) = crop.dimensions();
+    let (rgb, alpha): (Vec<[u8; 3]>, Vec<u8>) = crop.buffer()
+        .pixels()
+        .map(|Rgba([r, g, b, a])| ([*r, *g, *b], *a))
+        .unzip();
+    let rgb = compress_to_vec_zlib(&rgb.concat(), LEVEL);
+    let alpha = compress_to_vec_zlib(&alpha, LEVEL);
+
+    let mut image = pdf.image_xobject(image_id, &rgb);
+    image.filter(Filter::FlateDecode);
+    image.width(width as i32);
+    image.height(height as i32);
+    image.color_space().device_rgb();
+    image.bits_per_component(8);
+    image.s_mask(s_mask_id);
+    image.finish();
+
+    let mut s_mask = pdf.image_xobject(s_mask_id, &alpha);
+    s_mask.filter(Filter::FlateDecode);
+    s_mask.width(width as i32);
+    s_mask.height(height as i32);
+    s_mask.color_space().device_gray();
+    s_mask.bits_per_component(8);
+    s_mask.finish();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn crop(name: &str, width: u32, height: u32) -> Image {
+        Image::from_buffer(
+            RgbaImage::new(width, height),
+            format!("{}.png", name)
+        )
+    }
+
+    fn positions(placements: &[Placement]) -> Vec<(usize, u32, u32)> {
+        placements.iter()
+            .map(|placement| (placement.crop, placement.x, placement.y))
+            .collect()
+    }
+
+    #[test]
+    fn grid_fills_the_columns_and_splits_the_pages() {
+        let crops: Vec<Image> = (0..7)
+            .map(|idx| crop(&idx.to_string(), 100, 100))
+            .collect();
+        let mut sheet = ContactSheet {
+            page_height: Some(500),
+            ..ContactSheet::default()
+        };
+
+        // Cells of (1240 - 2 * 60 - 2 * 30) / 3 = 353 pixels
+        let rows = sheet.rows(&crops);
+        let lengths: Vec<usize> = rows.iter().map(Vec::len).collect();
+        assert_eq!(lengths, [3, 3, 1]);
+        assert_eq!(positions(&rows[1]), [(3, 60, 0), (4, 443, 0), (5, 826, 0)]);
+
+        // Rows of 100 + 36 pixels of caption, the third doesn't fit
+        let pages = sheet.pages(&crops);
+        assert_eq!(pages.len(), 2);
+        assert_eq!(pages[0].height, 500);
+        assert_eq!(pages[0].placements.len(), 6);
+        assert_eq!(pages[0].placements[3].y, 60 + 136 + 30);
+        assert_eq!(positions(&pages[1].placements), [(6, 60, 60)]);
+
+        sheet.page_height = None;
+        let pages = sheet.pages(&crops);
+        assert_eq!(pages.len(), 1);
+        assert_eq!(pages[0].height, 60 + 3 * 136 + 2 * 30 + 60);
+    }
+
+    #[test]
+    fn flow_wraps_the_crops_that_dont_fit() {
+        let crops = [
+            crop("a", 500, 100),
+            crop("b", 500, 80),
+            crop("c", 500, 100),
+            crop("d", 200, 100),
+        ];
+        let sheet = ContactSheet {
+            layout: Layout::Flow,
+            ..ContactSheet::default()
+        };
+
+        let rows = sheet.rows(&crops);
+        assert_eq!(rows.len(), 2);
+        assert_eq!(positions(&rows[0]), [(0, 60, 0), (1, 590, 0)]);
+        assert_eq!(positions(&rows[1]), [(2, 60, 0), (3, 590, 0)]);
+
+        let pages = sheet.pages(&crops);
+        assert_eq!(pages.len(), 1);
+        assert_eq!(positions(&pages[0].placements)[2], (2, 60, 60 + 136 + 30));
+        assert_eq!(pages[0].height, 60 + 2 * 136 + 30 + 60);
+    }
+
+    #[test]
+    fn flow_scales_down_crops_wider_than_the_sheet() {
+        let sheet = ContactSheet {
+            layout: Layout::Flow,
+            ..ContactSheet::default()
+        };
+
+        let rows = sheet.rows(&[crop("wide", 2240, 200)]);
+        assert_eq!((rows[0][0].width, rows[0][0].height), (1120, 100));
+    }
+
+    #[test]
+    fn captions_are_shortened_to_fit() {
+        let sheet = ContactSheet::default();
+        let short = crop("a", 10, 10);
+        let long = crop("a_rather_long_name_for_a_crop", 10, 10);
+
+        assert_eq!(sheet.caption(&short, 100, "..."), "a");
+
+        let caption = sheet.caption(&long, 100, "...");
+        assert!(caption.starts_with("a_"));
+        assert!(caption.ends_with("..."));
+        assert!(text::measure(&caption, sheet.caption_size).0 <= 100.0);
+
+        let caption = sheet.caption(&long, 100, "…");
+        assert!(caption.starts_with("a_") && caption.ends_with('…'));
+        assert!(text::measure(&caption, sheet.caption_size).0 <= 100.0);
+    }
+}
@@ -25,9 +25,11 @@ impl Crosshair {
         // Create paint color and stroke
         let mut paint = Paint::default();
         paint.set_color_rgba8(0, 10, 30, 255);
-        let mut stroke = Stroke::default();
-        stroke.width = 2.0;
-        stroke.dash = StrokeDash::new(vec![1.0; 20], 1.0);
+        let stroke = Stroke {
+            width: 2.0,
+            dash: StrokeDash::new(vec![1.0; 20], 1.0),
+            ..Stroke::default()
+        };
 
         pixmap.stroke_path(
             &self.paths.0,
@@ -0,0 +1,65 @@
+use std::path::PathBuf;
+
+/// Errors produced while cropping
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Image error: {0}")]
+    Image(#[from] image::ImageError),
+
+    #[error("PNG encoding error: {0}")]
+    Png(#[from] png::EncodingError),
+
+    #[error("TIFF encoding error: {0}")]
+    Tiff(#[from] tiff::TiffError),
+
+    #[error("The cropper needs at least one image")]
+    NoPages,
+
+    #[error("Couldn't create the window: {0}")]
+    Window(#[from] winit::error::OsError),
+
+    #[error("Couldn't render the window: {0}")]
+    Render(#[from] pixels::Error),
+
+    #[error("`pdfimages` failed: {0}")]
+    Pdfimages(String),
+
+    #[error("Region `{0}` is incomplete")]
+    IncompleteRegion(String),
+
+    #[error("Region `{name}` is too small ({width}x{height} pixels)")]
+    DegenerateRegion {
+        name: String,
+        width: u32,
+        height: u32
+    },
+
+    #[error("Region `{0}` is outside the image")]
+    OutsideImage(String),
+
+    #[error("Can't resample {0:?}, its density is unknown")]
+    UnknownDpi(PathBuf),
+
+    #[error("Invalid resample factor {0}x{1}")]
+    InvalidScale(f32, f32),
+
+    #[error("Invalid file name {0:?}")]
+    InvalidFileName(PathBuf),
+
+    #[error("Invalid palette file {path:?}: {message}")]
+    Palette {
+        path: PathBuf,
+        message: String
+    },
+
+    #[error("Invalid template file {path:?}: {message}")]
+    Template {
+        path: PathBuf,
+        message: String
+    },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
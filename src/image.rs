@@ -1,48 +1,112 @@
+use std::fs::File;
+use std::io::BufWriter;
 use std::path::{PathBuf, Path};
 
-use anyhow::*;
 use image::{Rgba, RgbaImage};
+use image::imageops::FilterType;
 use image::io::Reader as ImageReader;
 
+use crate::error::{Error, Result};
 use crate::regions::{RegionState, Region};
 
 const BLACK: Rgba<u8> = Rgba([0, 0, 0, 255]);
-const WHITE: Rgba<u8> = Rgba([255, 255, 255, 255]);
 const TRANSPARENT: Rgba<u8> = Rgba([0, 0, 0, 0]);
 
+/// The PNG `pHYs` chunk stores the density in pixels per meter
+const INCHES_PER_METER: f32 = 39.370_08;
+
 fn is_color(
-    match_color: Rgba<u8>, 
-    in_color: Rgba<u8>, 
+    match_color: Rgba<u8>,
+    in_color: Rgba<u8>,
     margin: u8
 ) -> bool {
     for i in 0..3 {
-        if in_color[i] <= match_color[i].saturating_add(margin) 
+        if in_color[i] <= match_color[i].saturating_add(margin)
                 && in_color[i] >= match_color[i].saturating_sub(margin) {
             return true;
         }
     }
 
-    return false;
+    false
+}
+
+/// If `pixel` is part of the ink of a plot, the ink is black when there is
+/// no key color, otherwise every channel of `pixel` has to be within
+/// `threshold` of the key, as a single one would match white paper with any
+/// saturated key
+pub(crate) fn is_ink(
+    pixel: Rgba<u8>,
+    key: Option<egui::Color32>,
+    threshold: u8
+) -> bool {
+    match key {
+        Some(key) => key.to_array()[..3].iter()
+            .zip(&pixel.0[..3])
+            .all(|(key, channel)| key.abs_diff(*channel) <= threshold),
+        None => is_color(BLACK, pixel, threshold),
+    }
 }
 
+/// How an image should be resampled before being saved
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Resample {
+    /// Keep the original resolution
+    None,
+
+    /// Multiply both dimensions by a factor
+    Scale(f32),
+
+    /// Resample to reach a target density in dots per inch, requires the
+    /// source density to be known
+    Dpi(f32),
+}
+
+/// An RGBA image together with the file it came from, or the file it will
+/// be saved as
 #[derive(Clone)]
 pub struct Image {
     image_buffer: RgbaImage,
     path: PathBuf,
+
+    /// Human readable name, the file name for loaded images and the region
+    /// name for crops
+    name: String,
+
+    /// Density in dots per inch (x, y), if known
+    dpi: Option<(f32, f32)>,
 }
 
 impl Image {
+    /// Load an image from a file
     pub fn new(path: impl AsRef<Path>) -> Result<Self> {
         let image_buffer = ImageReader::open(path.as_ref())?
             .decode()?
             .into_rgba8();
 
-        Ok(Self {
+        Ok(Self::from_buffer(image_buffer, path))
+    }
+
+    /// Wrap an in memory pixel buffer, `path` is the file name it will be
+    /// saved as
+    pub fn from_buffer(
+        image_buffer: RgbaImage,
+        path: impl AsRef<Path>
+    ) -> Self {
+        let name = path.as_ref()
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        Self {
             image_buffer,
-            path: path.as_ref().to_owned()
-        })
+            path: path.as_ref().to_owned(),
+            name,
+            dpi: None
+        }
     }
 
+    /// Fast resize by `ratio` with nearest neighbour sampling, used for
+    /// display
     pub fn resize(&mut self, ratio: f32) {
         let (mut new_width, mut new_height) = self.dimensions();
         new_width = (new_width as f32 * ratio) as u32;
@@ -56,46 +120,96 @@ impl Image {
         self.image_buffer = new_image_buffer;
     }
 
+    /// Resample the image using `filter`, the density is updated so the
+    /// physical size of the image is kept
+    pub fn resample(
+        &mut self,
+        resample: Resample,
+        filter: FilterType
+    ) -> Result<()> {
+        let (scale_x, scale_y) = match resample {
+            Resample::None => return Ok(()),
+            Resample::Scale(scale) => (scale, scale),
+            Resample::Dpi(target) => {
+                let (dpi_x, dpi_y) = self.dpi
+                    .ok_or_else(|| Error::UnknownDpi(self.path.clone()))?;
+                (target / dpi_x, target / dpi_y)
+            }
+        };
+        if !(scale_x > 0.0 && scale_y > 0.0) {
+            return Err(Error::InvalidScale(scale_x, scale_y));
+        }
+
+        let (width, height) = self.dimensions();
+        let new_width = ((width as f32 * scale_x).round() as u32).max(1);
+        let new_height = ((height as f32 * scale_y).round() as u32).max(1);
+        self.image_buffer = image::imageops::resize(
+            &self.image_buffer,
+            new_width, new_height,
+            filter
+        );
+        self.dpi = self.dpi.map(|(x, y)| (x * scale_x, y * scale_y));
+
+        Ok(())
+    }
+
+    /// Crop `region` out of the image, keeping only the ink recolored with
+    /// the region color, `counter` is appended to the file name of the crop,
+    /// the part of the region off the image is left out
+    ///
+    /// Fails if the region is incomplete, has no area or is entirely outside
+    /// the image
     pub fn extract_region(
         &self,
         counter: u32,
-        ratio: f32,
-        region: Region
-    ) -> Self {
+        region: &Region
+    ) -> Result<Self> {
         let mut path = self.path.clone();
-        let name = format!("{}-{}.png", 
-            path.file_stem().unwrap().to_str().unwrap(),
-            counter);
-        path.set_file_name(name);
-
-        let (start_row, start_col, width, height) = match region {
-            Region {
-                state: RegionState::Complete {
-                    x1,
-                    y1,
-                    x2,
-                    y2
-                },
-                color
-            } => {
-                (
-                    (x1 as f32 / ratio) as u32,
-                    (y1 as f32 / ratio) as u32,
-                    ((x2 - x1) as f32 / ratio) as u32,
-                    ((y2 - y1) as f32 / ratio) as u32
-                )
-            },
-            _ => panic!("Unexpected incomplete region")
-        };
+        let stem = path.file_stem()
+            .ok_or_else(|| Error::InvalidFileName(self.path.clone()))?
+            .to_string_lossy()
+            .into_owned();
+        path.set_file_name(format!("{}-{}.png", stem, counter));
+
+        let (x1, y1, x2, y2) = region.bounds()
+            .ok_or_else(|| Error::IncompleteRegion(region.name.clone()))?;
+        let (width, height) = ((x2 - x1) as u32, (y2 - y1) as u32);
+        if width == 0 || height == 0 {
+            return Err(Error::DegenerateRegion {
+                name: region.name.clone(),
+                width,
+                height
+            });
+        }
+
+        // Only the part of the region on the image is cropped
+        let (image_width, image_height) = self.dimensions();
+        let (start_row, end_row) = (
+            x1.clamp(0.0, image_width as f32) as u32,
+            x2.clamp(0.0, image_width as f32) as u32
+        );
+        let (start_col, end_col) = (
+            y1.clamp(0.0, image_height as f32) as u32,
+            y2.clamp(0.0, image_height as f32) as u32
+        );
+        if end_row <= start_row || end_col <= start_col {
+            return Err(Error::OutsideImage(region.name.clone()));
+        }
 
         let mut new_image_buffer = image::imageops::crop_imm(
-            &self.image_buffer, 
+            &self.image_buffer,
             start_row, start_col,
-            width, height
+            end_row - start_row, end_col - start_col
         ).to_image();
 
-        for color in new_image_buffer.pixels_mut() {
-            if is_color(BLACK, *color, 200) {
+        // Polygons only keep the ink whose pixel centers are inside them
+        let polygon = matches!(region.state, RegionState::Polygon { .. });
+        for (x, y, color) in new_image_buffer.enumerate_pixels_mut() {
+            let inside = !polygon || region.contains(
+                (start_row + x) as f32 + 0.5,
+                (start_col + y) as f32 + 0.5
+            );
+            if inside && is_ink(*color, region.key_color, region.threshold) {
                 let (r, g, b, a) = region.color.to_tuple();
                 *color = Rgba([r, g, b, a]);
             } else {
@@ -103,24 +217,177 @@ impl Image {
             }
         }
 
-        Self {
+        Ok(Self {
             image_buffer: new_image_buffer,
-            path
+            path,
+            name: region.name.clone(),
+            dpi: self.dpi
+        })
+    }
+
+    /// Save the image on `out_dir` with the name of its file, the format is
+    /// deduced from the extension, PNG and TIFF outputs also store the
+    /// density of the image
+    pub fn save(&self, out_dir: &Path) -> Result<()> {
+        let file_name = self.path.file_name()
+            .ok_or_else(|| Error::InvalidFileName(self.path.clone()))?;
+        let final_path = out_dir.join(file_name);
+        match final_path.extension().and_then(|ext| ext.to_str()) {
+            Some("png") => self.save_png(&final_path)?,
+            Some("tif" | "tiff") => self.save_tiff(&final_path)?,
+            _ => self.image_buffer.save(final_path)?,
         }
+
+        Ok(())
     }
 
-    pub fn save(&self, out_dir: &PathBuf) -> Result<()> {
-        let final_path = out_dir.join(&self.path);
-        dbg!(&final_path);
-        self.image_buffer.save(final_path)?;
+    fn save_png(&self, path: &Path) -> Result<()> {
+        let (width, height) = self.dimensions();
+        let writer = BufWriter::new(File::create(path)?);
+        let mut encoder = png::Encoder::new(writer, width, height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        if let Some((dpi_x, dpi_y)) = self.dpi {
+            encoder.set_pixel_dims(Some(png::PixelDimensions {
+                xppu: (dpi_x * INCHES_PER_METER).round() as u32,
+                yppu: (dpi_y * INCHES_PER_METER).round() as u32,
+                unit: png::Unit::Meter
+            }));
+        }
+        encoder.write_header()?
+            .write_image_data(self.as_bytes())?;
 
         Ok(())
     }
 
+    fn save_tiff(&self, path: &Path) -> Result<()> {
+        use tiff::encoder::{TiffEncoder, Rational, colortype::RGBA8};
+        use tiff::tags::ResolutionUnit;
+
+        let (width, height) = self.dimensions();
+        let writer = BufWriter::new(File::create(path)?);
+        let mut encoder = TiffEncoder::new(writer)?;
+        let mut image = encoder.new_image::<RGBA8>(width, height)?;
+        if let Some((dpi_x, dpi_y)) = self.dpi {
+            // Keep two decimals of precision on the stored rationals
+            image.resolution_unit(ResolutionUnit::Inch);
+            image.x_resolution(Rational {
+                n: (dpi_x * 100.0).round() as u32,
+                d: 100
+            });
+            image.y_resolution(Rational {
+                n: (dpi_y * 100.0).round() as u32,
+                d: 100
+            });
+        }
+        image.write_data(self.as_bytes())?;
+
+        Ok(())
+    }
+
+    /// Change the extension of the file this image will be saved as
+    pub fn set_extension(&mut self, extension: &str) {
+        self.path.set_extension(extension);
+    }
+
+    /// File the image will be saved as, relative to the output directory
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn set_name(&mut self, name: impl Into<String>) {
+        self.name = name.into();
+    }
+
+    /// The pixels of the image
+    pub fn buffer(&self) -> &RgbaImage {
+        &self.image_buffer
+    }
+
+    pub fn buffer_mut(&mut self) -> &mut RgbaImage {
+        &mut self.image_buffer
+    }
+
+    pub fn into_buffer(self) -> RgbaImage {
+        self.image_buffer
+    }
+
+    /// Density in dots per inch (x, y), if known
+    pub fn dpi(&self) -> Option<(f32, f32)> {
+        self.dpi
+    }
+
+    pub fn set_dpi(&mut self, dpi: Option<(f32, f32)>) {
+        self.dpi = dpi;
+    }
+
     pub fn dimensions(&self) -> (u32, u32) {
         self.image_buffer.dimensions()
     }
 
+    /// Average color of the `patch` x `patch` pixels centered at (x, y),
+    /// clipped to the image, `None` if the point is outside it
+    pub fn average_color(&self, x: u32, y: u32, patch: u32) -> Option<Rgba<u8>> {
+        let (width, height) = self.dimensions();
+        if x >= width || y >= height {
+            return None;
+        }
+
+        let half = patch.max(1) / 2;
+        let mut sum = [0u32; 4];
+        let mut count = 0;
+        for py in y.saturating_sub(half)..=(y + half).min(height - 1) {
+            for px in x.saturating_sub(half)..=(x + half).min(width - 1) {
+                let pixel = self.image_buffer.get_pixel(px, py);
+                for (sum, channel) in sum.iter_mut().zip(pixel.0) {
+                    *sum += channel as u32;
+                }
+                count += 1;
+            }
+        }
+
+        Some(Rgba(sum.map(|sum| (sum / count) as u8)))
+    }
+
+    /// Bounding box (x1, y1, x2, y2) of the ink inside `bounds`, clipped to
+    /// the image, `None` if there is no ink
+    pub fn content_bounds(
+        &self,
+        (x1, y1, x2, y2): (f32, f32, f32, f32),
+        key: Option<egui::Color32>,
+        threshold: u8
+    ) -> Option<(f32, f32, f32, f32)> {
+        let (width, height) = self.dimensions();
+        let clip = |value: f32, max: u32| value.clamp(0.0, max as f32) as u32;
+        let (x1, x2) = (clip(x1, width), clip(x2.ceil(), width));
+        let (y1, y2) = (clip(y1, height), clip(y2.ceil(), height));
+
+        let mut content: Option<(u32, u32, u32, u32)> = None;
+        for y in y1..y2 {
+            for x in x1..x2 {
+                if !is_ink(*self.image_buffer.get_pixel(x, y), key, threshold) {
+                    continue;
+                }
+                content = Some(match content {
+                    Some((cx1, cy1, cx2, cy2)) => {
+                        (cx1.min(x), cy1.min(y), cx2.max(x), cy2.max(y))
+                    }
+                    None => (x, y, x, y)
+                });
+            }
+        }
+
+        // The last pixel with ink is included
+        content.map(|(x1, y1, x2, y2)| {
+            (x1 as f32, y1 as f32, (x2 + 1) as f32, (y2 + 1) as f32)
+        })
+    }
+
+    /// Raw RGBA bytes of the image, row by row
     pub fn as_bytes(&self) -> &[u8] {
         self.image_buffer.as_raw()
     }
@@ -1,60 +1,165 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
 use winit::window::WindowBuilder;
-use winit::event_loop::{EventLoop, ControlFlow};
+use winit::event_loop::{EventLoop, EventLoopBuilder, ControlFlow};
 use winit::event::*;
 use winit::dpi::{PhysicalSize, PhysicalPosition, LogicalSize}; 
 use winit::platform::run_return::EventLoopExtRunReturn;
-use pixels::{Pixels, wgpu, PixelsBuilder, SurfaceTexture};
+use pixels::{PixelsBuilder, SurfaceTexture};
+use image::{imageops, Rgba, RgbaImage};
 
+use crate::error::{Error, Result};
+use crate::color_picker::PromptAnswer;
+use crate::page_strip::PageStrip;
 use crate::renderer::MasterRenderer;
 use crate::image::Image;
+use crate::palette::Palette;
+use crate::preprocess::Preprocess;
+use crate::regions::{Crop, IncompletePolicy, Regions};
+use crate::watch::Watcher;
+
+/// Window size limit used when the monitor size can't be queried
+const FALLBACK_MONITOR_SIZE: PhysicalSize<u32> = PhysicalSize {
+    width: 1280,
+    height: 720
+};
+
+/// Events sent to the window from other threads
+pub enum CropperEvent {
+    /// The watched file changed, with the image loaded again from it
+    SourceChanged(Image),
+}
+
+/// Image open on the cropper, before preprocessing, with the regions drawn
+/// over it, both are `None` while the page is shown as they are moved to the
+/// cropper and the renderer
+struct Page {
+    image: Option<Image>,
+    regions: Option<Regions>,
+}
+
+/// Loads the image again when the watched file changes, `None` if it
+/// couldn't, the image shown is kept then
+///
+/// It's called on the thread watching the file, not to block the window.
+type Reload = Box<dyn FnMut() -> Option<Image> + Send>;
 
-/// Entry of the image cropper
+/// Entry of the image cropper, a window where the user draws the regions to
+/// crop over an image
 pub struct ImageCropper {
     /// Window that we created
-    pub window: winit::window::Window,
+    pub(crate) window: winit::window::Window,
 
     /// The event loop
-    pub event_loop: Option<EventLoop<()>>,
+    pub(crate) event_loop: Option<EventLoop<CropperEvent>>,
 
     /// Logical width of the inner part of the window
-    pub width: u32,
+    pub(crate) width: u32,
 
     /// Logical height of the inner part of the window
-    pub height: u32,
+    pub(crate) height: u32,
 
     /// Scale ratio
-    pub ratio: f32,
+    pub(crate) ratio: f32,
 
     /// Pixels buffer
-    pub pixbuf: pixels::Pixels,
+    pub(crate) pixbuf: pixels::Pixels,
+
+    /// The loaded image to edit, the one of the page shown preprocessed
+    pub(crate) image: Image,
 
-    /// The loaded image to edit
-    pub image: Image,
+    /// The page shown as it was loaded
+    source: Image,
+
+    /// Cleanup applied to the pages before their ink is keyed
+    pub(crate) preprocess: Preprocess,
+
+    /// Every image open, to switch between them
+    pages: Vec<Page>,
+
+    /// Index of the page shown
+    page: usize,
+
+    /// Thumbnails of the pages, moved to the renderer once it's created
+    pub(crate) strip: PageStrip,
 
     /// Resized image to render
-    pub render_image: Image,
+    pub(crate) render_image: Image,
+
+    /// Regions to start with, moved to the renderer once it's created
+    pub(crate) regions: Option<Regions>,
 
-    /// The cropped colored images
-    image_crops: Vec<Image>,
+    /// Palettes offered to color the regions, moved to the renderer once
+    /// it's created
+    pub(crate) palettes: Vec<Palette>,
+
+    /// What to do with an incomplete region when the window is closed
+    incomplete_policy: IncompletePolicy,
+
+    /// File watched to reload the image in place when it changes
+    watch: Option<(PathBuf, Reload)>,
+
+    /// Every page preprocessed with its cropped colored images, or the error
+    /// that stopped the cropper
+    image_crops: Result<Vec<(Image, Vec<Crop>)>>,
 
     /// The container and manager of all the renderers
     renderer: Option<MasterRenderer>,
 }
 
 impl ImageCropper {
+    /// Open a window to crop `image`
     pub fn new(
         image: Image
-    ) -> Self {
-        let event_loop = EventLoop::new();
+    ) -> Result<Self> {
+        Self::with_regions(image, Regions::new())
+    }
 
-        // Extract main monitor size and image dimensions
+    /// Open a window to crop `image` with some regions already drawn
+    pub fn with_regions(
+        image: Image,
+        regions: Regions
+    ) -> Result<Self> {
+        Self::with_pages(vec![(image, regions)])
+    }
+
+    /// Open a window to crop several images, each with its own regions,
+    /// a strip of thumbnails and PageUp and PageDown switch between them
+    ///
+    /// Fails if there are no images.
+    pub fn with_pages(pages: Vec<(Image, Regions)>) -> Result<Self> {
+        let strip = PageStrip::new(pages.iter()
+            .map(|(image, regions)| (image, regions.len())));
+        let mut pages: Vec<Page> = pages.into_iter()
+            .map(|(image, regions)| Page {
+                image: Some(image),
+                regions: Some(regions)
+            })
+            .collect();
+        let first = pages.first_mut().ok_or(Error::NoPages)?;
+        let image = first.image.take().unwrap();
+        let regions = first.regions.take().unwrap();
+
+        let event_loop = EventLoopBuilder::with_user_event().build();
+
+        // Extract main monitor size and image dimensions, some platforms
+        // (headless, Wayland) don't report a primary monitor
         let PhysicalSize {
             width: monitor_width,
             height: monitor_height
         } = event_loop.primary_monitor()
+            .or_else(|| event_loop.available_monitors().next())
             .map(|monitor| monitor.size())
-            .unwrap();
-        let (mut window_width, mut window_height) = image.dimensions();
+            .unwrap_or(FALLBACK_MONITOR_SIZE);
+        // Fit the largest page, the smaller ones leave a margin
+        let (mut window_width, mut window_height) = pages.iter()
+            .filter_map(|page| page.image.as_ref())
+            .map(Image::dimensions)
+            .fold(image.dimensions(), |(aw, ah), (bw, bh)| {
+                (aw.max(bw), ah.max(bh))
+            });
 
         // Calculate the ratio
         let mut ratio = 1.0;
@@ -70,8 +175,7 @@ impl ImageCropper {
             .with_inner_size(
                 LogicalSize::new(window_width, window_height))
             .with_title("Image Cropper")
-            .build(&event_loop)
-            .unwrap();
+            .build(&event_loop)?;
 
         // Get the inner physical size of the window and store it
         let PhysicalSize { 
@@ -80,33 +184,193 @@ impl ImageCropper {
         } = window.inner_size();
 
 
-        let mut render_image = image.clone();
-        render_image.resize(ratio);
+        let render_image = render_image(&image, ratio, (width, height));
 
         // Create the pixels buffer
-        let mut pixbuf = {
+        let pixbuf = {
             let surface_texture = SurfaceTexture::new(width, height, &window);
             PixelsBuilder::new(width, height, surface_texture)
-                .build().unwrap()
+                .build()?
         };
 
-        Self {
+        Ok(Self {
             window,
             event_loop: Some(event_loop),
             width,
             height,
             ratio,
-            image,
+            image: image.clone(),
+            source: image,
+            preprocess: Preprocess::default(),
+            pages,
+            page: 0,
+            strip,
             render_image,
             pixbuf,
-            image_crops: Vec::new(),
+            regions: Some(regions),
+            palettes: Vec::new(),
+            incomplete_policy: IncompletePolicy::Prompt,
+            watch: None,
+            image_crops: Ok(Vec::new()),
             renderer: None
+        })
+    }
+
+    /// Set what to do with an incomplete region when the window is closed,
+    /// by default the user is asked
+    pub fn incomplete_policy(mut self, policy: IncompletePolicy) -> Self {
+        self.incomplete_policy = policy;
+        self
+    }
+
+    /// Set the palettes offered to color the regions
+    pub fn palettes(mut self, palettes: Vec<Palette>) -> Self {
+        self.palettes = palettes;
+        self
+    }
+
+    /// Clean up the pages with `preprocess` before keying their ink, it can
+    /// be changed from the window
+    pub fn preprocess(mut self, preprocess: Preprocess) -> Self {
+        self.preprocess = preprocess;
+        self.image = preprocess.apply(&self.source);
+        self.render_image = render_image(
+            &self.image,
+            self.ratio,
+            (self.width, self.height)
+        );
+        self
+    }
+
+    /// Watch the file on `path` while the window is open and replace the
+    /// image of the first page with the one returned by `reload` whenever it
+    /// changes, the regions are kept
+    ///
+    /// `reload` runs on the thread watching the file. The new image must
+    /// have the same size, otherwise it's ignored.
+    pub fn watch(
+        mut self,
+        path: impl Into<PathBuf>,
+        reload: impl FnMut() -> Option<Image> + Send + 'static
+    ) -> Self {
+        self.watch = Some((path.into(), Box::new(reload)));
+        self
+    }
+
+    /// Replace the first page with `image`, loaded again after the watched
+    /// file changed
+    fn reload(&mut self, renderer: &mut MasterRenderer, image: Image) {
+        let old = self.pages[0].image.as_mut().unwrap_or(&mut self.source);
+        if image.dimensions() != old.dimensions() {
+            eprintln!("The new image is {:?} instead of {:?}, keeping the \
+                old one", image.dimensions(), old.dimensions());
+            return;
+        }
+
+        *old = image;
+        if self.page == 0 {
+            self.source_changed(renderer);
+        }
+    }
+
+    /// Preprocess the page shown again with `preprocess`
+    pub(crate) fn set_preprocess(
+        &mut self,
+        renderer: &mut MasterRenderer,
+        preprocess: Preprocess
+    ) {
+        self.preprocess = preprocess;
+        self.source_changed(renderer);
+    }
+
+    /// Preprocess and render the page shown after it was replaced
+    fn source_changed(&mut self, renderer: &mut MasterRenderer) {
+        self.image = self.preprocess.apply(&self.source);
+        self.render_image = render_image(
+            &self.image,
+            self.ratio,
+            (self.width, self.height)
+        );
+        renderer.image_changed(self);
+    }
+
+    /// Number of pages open
+    pub fn page_count(&self) -> usize {
+        self.pages.len()
+    }
+
+    /// Index of the page shown
+    pub fn current_page(&self) -> usize {
+        self.page
+    }
+
+    /// Show the page `idx` with its regions, the region being drawn on the
+    /// page shown is dropped
+    pub(crate) fn show_page(&mut self, renderer: &mut MasterRenderer, idx: usize) {
+        if idx == self.page || idx >= self.pages.len() {
+            return;
         }
+
+        renderer.regions.cancel();
+        let page = &mut self.pages[idx];
+        let image = page.image.take().unwrap();
+        let regions = page.regions.take().unwrap();
+        renderer.color_picker.strip.counts[self.page] = renderer.regions.len();
+        self.pages[self.page] = Page {
+            image: Some(std::mem::replace(&mut self.source, image)),
+            regions: Some(std::mem::replace(&mut renderer.regions, regions))
+        };
+        self.page = idx;
+        renderer.color_picker.strip.current = idx;
+
+        self.source_changed(renderer);
+    }
+
+    /// Validate the regions and crop them, unless the user has to be asked
+    /// first about an incomplete region
+    fn close(
+        &mut self,
+        renderer: &mut MasterRenderer,
+        control_flow: &mut ControlFlow
+    ) {
+        if !renderer.regions.is_finished()
+                && self.incomplete_policy == IncompletePolicy::Prompt {
+            renderer.color_picker.prompt_incomplete = true;
+            renderer.request_redraw(self);
+            return;
+        }
+
+        // Crop every page, the regions of the one shown are on the renderer
+        let mut crops = Vec::new();
+        for page in &mut self.pages {
+            let regions = page.regions.as_mut().unwrap_or(&mut renderer.regions);
+            let image = match &page.image {
+                Some(image) => self.preprocess.apply(image),
+                None => self.image.clone()
+            };
+            for region in regions.validate() {
+                if self.incomplete_policy != IncompletePolicy::Drop {
+                    eprintln!("Discarding region `{}`, it's incomplete or too \
+                        small", region.name);
+                }
+            }
+            match regions.get_image_crops(&image) {
+                Ok(page_crops) => crops.push((image, page_crops)),
+                Err(err) => {
+                    self.image_crops = Err(err);
+                    *control_flow = ControlFlow::Exit;
+                    return;
+                }
+            }
+        }
+
+        self.image_crops = Ok(crops);
+        *control_flow = ControlFlow::Exit;
     }
 
     pub fn handle_event(
         &mut self,
-        event: Event<'_, ()>,
+        event: Event<'_, CropperEvent>,
         control_flow: &mut ControlFlow
     ) {
         *control_flow = ControlFlow::Wait;
@@ -123,14 +387,13 @@ impl ImageCropper {
                 } = self.window.inner_size();
                 self.pixbuf.resize_surface(width, height);
                 self.pixbuf.resize_buffer(width, height);
-                dbg!(self.window.inner_size());
-                dbg!(self.render_image.dimensions());
                 self.pixbuf.get_frame_mut()
                     .copy_from_slice(self.render_image.as_bytes());
 
                 renderer.buff_render(self);
 
-                self.pixbuf.render_with(|encoder, render_target, context| {
+                let rendered = self.pixbuf.render_with(
+                        |encoder, render_target, context| {
                     context.scaling_renderer.render(encoder, render_target);
 
                     renderer.gpu_render(
@@ -141,24 +404,45 @@ impl ImageCropper {
                     );
 
                     Ok(())
-                }).unwrap();
+                });
+                if let Err(err) = rendered {
+                    self.image_crops = Err(err.into());
+                    *control_flow = ControlFlow::Exit;
+                }
+            }
+            Event::UserEvent(CropperEvent::SourceChanged(image)) => {
+                self.reload(&mut renderer, image);
             }
             Event::WindowEvent {
                 ref event,
                 ..
             } => {
+                // Drags end even if the button is released over the panel
+                if let WindowEvent::MouseInput {
+                    state: ElementState::Released,
+                    button: MouseButton::Left,
+                    ..
+                } = event {
+                    renderer.mouse_left_release(self);
+                }
+
                 if renderer.color_picker.handle_event(event) {
                     renderer.request_redraw(self);
+                    if let Some(PromptAnswer::Discard) =
+                            renderer.color_picker.prompt_answer.take() {
+                        self.incomplete_policy = IncompletePolicy::Drop;
+                        self.close(&mut renderer, control_flow);
+                    }
+                    if let Some(idx) = renderer.color_picker.strip.request.take() {
+                        self.show_page(&mut renderer, idx);
+                    }
                     self.renderer = Some(renderer);
                     return;
                 }
 
                 match event {
                     WindowEvent::CloseRequested => {
-                        self.image_crops = 
-                            renderer.regions.get_image_crops(self.ratio,
-                                                             &self.image);
-                        *control_flow = ControlFlow::Exit;
+                        self.close(&mut renderer, control_flow);
                     }
                     WindowEvent::CursorMoved {
                         position: PhysicalPosition { x, y },
@@ -179,6 +463,19 @@ impl ImageCropper {
                         }
                         _ => {}
                     }
+                    WindowEvent::KeyboardInput {
+                        input: KeyboardInput {
+                            state: ElementState::Pressed,
+                            virtual_keycode: Some(key),
+                            ..
+                        },
+                        ..
+                    } => {
+                        renderer.key_pressed(self, *key);
+                    }
+                    WindowEvent::ModifiersChanged(modifiers) => {
+                        renderer.modifiers_changed(self, *modifiers);
+                    }
                     _ => {}
                 };
             }
@@ -188,25 +485,78 @@ impl ImageCropper {
         self.renderer = Some(renderer);
     }
 
-    pub fn run(mut self) -> anyhow::Result<Vec<Image>> {
+    /// Show the window till it's closed
+    ///
+    /// Returns the crop of every region drawn, on every page
+    pub fn run(self) -> Result<Vec<Crop>> {
+        Ok(self.run_pages()?
+            .into_iter()
+            .flat_map(|(_, crops)| crops)
+            .collect())
+    }
+
+    /// Show the window till it's closed
+    ///
+    /// Returns the image of every page, its last version if it's watched and
+    /// preprocessed, with the crops of the regions drawn on it
+    pub fn run_pages(mut self) -> Result<Vec<(Image, Vec<Crop>)>> {
         // Register the event handler
         self.renderer = Some(MasterRenderer::create(&mut self));
 
-        // Handle events forever unless we get an error or the application
-        // should exit
-        loop {
-            if let Some(mut event_loop) = self.event_loop.take() {
-                if event_loop.run_return(|event, _, control_flow| {
-                    self.handle_event(event, control_flow);
-                }) != 0 {
+        // Poll and load the watched file on another thread, it ends once the
+        // window is closed
+        let open = Arc::new(AtomicBool::new(true));
+        let mut watching = None;
+        if let (Some((path, mut reload)), Some(event_loop)) =
+                (self.watch.take(), &self.event_loop) {
+            let mut watcher = Watcher::new(path);
+            let proxy = event_loop.create_proxy();
+            let open = Arc::clone(&open);
+            watching = Some(std::thread::spawn(move || loop {
+                if !watcher.wait_while(&open) {
+                    break;
+                }
+                let image = match reload() {
+                    Some(image) => image,
+                    None => continue
+                };
+                if proxy.send_event(CropperEvent::SourceChanged(image)).is_err() {
                     break;
                 }
-            } else {
+            }));
+        }
+
+        // Handle events forever unless we get an error or the application
+        // should exit
+        while let Some(mut event_loop) = self.event_loop.take() {
+            if event_loop.run_return(|event, _, control_flow| {
+                self.handle_event(event, control_flow);
+            }) != 0 {
                 break;
             }
         }
+        open.store(false, Ordering::Relaxed);
+        if let Some(watching) = watching {
+            let _ = watching.join();
+        }
 
-        Ok(self.image_crops)
+        self.image_crops
     }
 }
 
+/// `image` scaled by `ratio` to be rendered on a window of `size`, cut or
+/// extended with a gray margin if it doesn't fill it
+fn render_image(image: &Image, ratio: f32, (width, height): (u32, u32)) -> Image {
+    let mut render_image = image.clone();
+    render_image.resize(ratio);
+    if render_image.dimensions() == (width, height) {
+        return render_image;
+    }
+
+    let mut canvas = RgbaImage::from_pixel(width, height, Rgba([64, 64, 64, 255]));
+    imageops::replace(&mut canvas, render_image.buffer(), 0, 0);
+    *render_image.buffer_mut() = canvas;
+
+    render_image
+}
+
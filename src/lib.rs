@@ -1,9 +1,59 @@
+//! Crop the plots of a pdf and recolor their ink.
+//!
+//! The cropper can be embedded in other tools, either showing the window so
+//! the user draws the regions, or headlessly with regions built in code:
+//!
+//! ```no_run
+//! use plotview::{Color32, Image, Region, Regions};
+//!
+//! let image = Image::new("img-000.ppm")?;
+//! let mut regions = Regions::new();
+//! regions.push(Region::new(10.0, 10.0, 400.0, 300.0, "Figure 1")
+//!     .with_color(Color32::RED));
+//!
+//! for crop in regions.get_image_crops(&image)? {
+//!     println!("{} at {:?}", crop.region.name, crop.region.bounds());
+//!     crop.image.save("out".as_ref())?;
+//! }
+//! # Ok::<(), plotview::Error>(())
+//! ```
+//!
+//! Region coordinates are always in pixels of the original image.
+
 mod color_picker;
 mod regions;
 mod crosshair;
+mod loupe;
 mod renderer;
 mod image_cropper;
 mod image;
+mod text;
+mod contact_sheet;
+mod preview;
+mod panel;
+mod page_strip;
+mod preprocess;
+mod snap;
+mod spatial;
+mod palette;
+mod template;
+mod register;
+mod watch;
+mod error;
+pub mod pdfimages;
 
+pub use crate::error::{Error, Result};
 pub use crate::image_cropper::ImageCropper;
-pub use crate::image::Image;
+pub use crate::image::{Image, Resample};
+pub use crate::regions::{
+    Align, Constraint, Crop, IncompletePolicy, Region, RegionState, Regions,
+    DEFAULT_MIN_SIZE, DEFAULT_THRESHOLD
+};
+pub use crate::contact_sheet::{ContactSheet, Layout};
+pub use crate::palette::{ColorScheme, Palette};
+pub use crate::preprocess::{Binarize, Preprocess};
+pub use crate::template::Template;
+pub use crate::register::Alignment;
+pub use crate::watch::Watcher;
+pub use egui::Color32;
+pub use ::image::RgbaImage;
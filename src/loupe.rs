@@ -0,0 +1,158 @@
+use image::Rgba;
+use tiny_skia::*;
+
+use crate::image::Image;
+use crate::palette::to_hex;
+use crate::text;
+
+/// Size of the readout text, in pixels
+const READOUT_SIZE: f32 = 13.0;
+
+/// Distance from the cursor to the loupe, in pixels
+const CURSOR_OFFSET: f32 = 24.0;
+
+/// Zoomed view of the original image around the cursor, so regions can be
+/// placed on the exact pixel even when the image is shown downscaled
+pub struct Loupe {
+    /// Pixels of the original image shown on each side of the center one
+    radius: u32,
+
+    /// Size on screen of each pixel of the original image
+    zoom: f32,
+}
+
+impl Loupe {
+    pub fn new(radius: u32, zoom: f32) -> Self {
+        Self {
+            radius,
+            zoom
+        }
+    }
+
+    /// Draw the loupe next to `cursor`, in window coordinates, with the
+    /// position and color of the pixel under it
+    pub fn render(
+        &self,
+        pixmap: &mut PixmapMut,
+        image: &Image,
+        ratio: f32,
+        (cx, cy): (f32, f32)
+    ) {
+        let (width, height) = image.dimensions();
+        let (px, py) = ((cx / ratio) as i64, (cy / ratio) as i64);
+        if px < 0 || py < 0 || px >= width as i64 || py >= height as i64 {
+            return;
+        }
+
+        // Place the loupe below right of the cursor, or on the other side if
+        // it doesn't fit
+        let cells = 2 * self.radius + 1;
+        let side = cells as f32 * self.zoom;
+        let readout_height = text::measure("0", READOUT_SIZE).1 + 4.0;
+        let mut x = cx + CURSOR_OFFSET;
+        if x + side > pixmap.width() as f32 {
+            x = cx - CURSOR_OFFSET - side;
+        }
+        let mut y = cy + CURSOR_OFFSET;
+        if y + side + readout_height > pixmap.height() as f32 {
+            y = cy - CURSOR_OFFSET - side - readout_height;
+        }
+
+        // Every pixel of the patch as a square, outside the image is black
+        let mut paint = Paint::default();
+        let buffer = image.buffer();
+        for row in 0..cells {
+            for col in 0..cells {
+                let sx = px + col as i64 - self.radius as i64;
+                let sy = py + row as i64 - self.radius as i64;
+                let inside = sx >= 0 && sy >= 0
+                    && sx < width as i64 && sy < height as i64;
+                let Rgba([r, g, b, _]) = if inside {
+                    *buffer.get_pixel(sx as u32, sy as u32)
+                } else {
+                    Rgba([0, 0, 0, 255])
+                };
+                paint.set_color_rgba8(r, g, b, 255);
+                if let Some(rect) = Rect::from_xywh(
+                    x + col as f32 * self.zoom,
+                    y + row as f32 * self.zoom,
+                    self.zoom,
+                    self.zoom
+                ) {
+                    pixmap.fill_rect(rect, &paint, Transform::identity(), None);
+                }
+            }
+        }
+
+        // Pixel grid
+        let mut grid = PathBuilder::new();
+        for idx in 1..cells {
+            let offset = idx as f32 * self.zoom;
+            grid.move_to(x + offset, y);
+            grid.line_to(x + offset, y + side);
+            grid.move_to(x, y + offset);
+            grid.line_to(x + side, y + offset);
+        }
+        paint.set_color_rgba8(128, 128, 128, 96);
+        let grid_stroke = Stroke {
+            width: 1.0,
+            ..Stroke::default()
+        };
+        if let Some(grid) = grid.finish() {
+            pixmap.stroke_path(
+                &grid,
+                &paint,
+                &grid_stroke,
+                Transform::identity(),
+                None
+            );
+        }
+
+        // Frame and the pixel under the cursor
+        let frame_stroke = Stroke {
+            width: 2.0,
+            ..Stroke::default()
+        };
+        paint.set_color_rgba8(0, 10, 30, 255);
+        if let Some(frame) = Rect::from_xywh(x, y, side, side) {
+            pixmap.stroke_path(
+                &PathBuilder::from_rect(frame),
+                &paint,
+                &frame_stroke,
+                Transform::identity(),
+                None
+            );
+        }
+        paint.set_color_rgba8(255, 0, 0, 255);
+        let center = self.radius as f32 * self.zoom;
+        if let Some(cell) = Rect::from_xywh(
+            x + center, y + center, self.zoom, self.zoom
+        ) {
+            pixmap.stroke_path(
+                &PathBuilder::from_rect(cell),
+                &paint,
+                &frame_stroke,
+                Transform::identity(),
+                None
+            );
+        }
+
+        // Readout of the coordinates and color of the pixel under the cursor
+        let Rgba([r, g, b, _]) = *buffer.get_pixel(px as u32, py as u32);
+        let readout = format!("{}, {}  {}",
+            px, py, to_hex(egui::Color32::from_rgb(r, g, b)));
+        paint.set_color_rgba8(0, 10, 30, 220);
+        if let Some(background) = Rect::from_xywh(
+            x, y + side, side, readout_height
+        ) {
+            pixmap.fill_rect(background, &paint, Transform::identity(), None);
+        }
+        text::draw_on_pixmap(
+            pixmap,
+            &readout,
+            x + 4.0, y + side + 2.0,
+            READOUT_SIZE,
+            Rgba([255, 255, 255, 255])
+        );
+    }
+}
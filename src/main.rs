@@ -1,15 +1,81 @@
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::io::{BufRead, IsTerminal, Write};
 use std::process::Command;
 use std::fs::read_dir;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 
-use clap::Parser;
-use plotview::{Image, ImageCropper};
+use clap::{Parser, Subcommand, ValueEnum};
+use rayon::prelude::*;
+use image::imageops::FilterType;
+use plotview::pdfimages::{self, ImageInfo, PageRange, MIN_IMAGE_SIZE};
+use plotview::{
+    Binarize, ContactSheet, Crop, Image, ImageCropper, IncompletePolicy,
+    Layout, ColorScheme, Constraint, Palette, Preprocess, Regions, Resample,
+    Template, Watcher, DEFAULT_MIN_SIZE
+};
 use anyhow::{Context, Result, anyhow, bail};
 
-fn find_files(starts_with: &str, exclude_end: &str) -> Result<Vec<PathBuf>> {
+/// Hidden directory of the output directory with the files of plotview
+const STATE_DIR: &str = ".plotview";
+
+/// File of the state directory with the regions of the last run, a
+/// template to extract the crops again
+const SESSION_FILE: &str = "session";
+
+/// File of the output directory with the outcome of every pdf of a batch
+const REPORT_FILE: &str = "report.txt";
+
+/// Width of the progress bar of a batch, in characters
+const PROGRESS_WIDTH: usize = 30;
+
+/// Temporary directories created by this process, to name them apart
+static WORK_DIRS: AtomicUsize = AtomicUsize::new(0);
+
+/// Temporary directory where `pdfimages` extracts the images, removed with
+/// everything inside when dropped unless the files are kept to debug
+struct WorkDir {
+    path: PathBuf,
+    keep: bool,
+}
+
+impl WorkDir {
+    fn new(keep: bool) -> Result<Self> {
+        loop {
+            let path = std::env::temp_dir().join(format!("plotview-{}-{}",
+                std::process::id(), WORK_DIRS.fetch_add(1, Ordering::Relaxed)));
+            match std::fs::create_dir(&path) {
+                Ok(()) => return Ok(Self { path, keep }),
+                // Left by a previous process with the same id
+                Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {}
+                Err(err) => return Err(err)
+                    .context("Couldn't create the temporary directory"),
+            }
+        }
+    }
+}
+
+impl Drop for WorkDir {
+    fn drop(&mut self) {
+        if self.keep {
+            eprintln!("Kept the extracted images on {:?}", self.path);
+        } else if let Err(err) = std::fs::remove_dir_all(&self.path) {
+            eprintln!("Couldn't remove {:?}: {}", self.path, err);
+        }
+    }
+}
+
+fn find_files(
+    dir: &Path,
+    starts_with: &str,
+    exclude_end: &str
+) -> Result<Vec<PathBuf>> {
     let mut paths = Vec::new();
-    let mut current_dir_iter = read_dir(".")
-        .context("Couldn't read current directory")?;
+    let mut current_dir_iter = read_dir(dir)
+        .with_context(|| format!("Couldn't read directory {:?}", dir))?;
     while let Some(Ok(entry)) = current_dir_iter.next() {
         // If we don't have perms to read some file properties we don't crash,
         // we just ignore the file
@@ -20,9 +86,14 @@ fn find_files(starts_with: &str, exclude_end: &str) -> Result<Vec<PathBuf>> {
 
         // Check that its a file and stats with the provided pattern
         if file_type?.is_file() {
-            let file_name = entry.file_name().into_string().unwrap();
-            if file_name.starts_with(starts_with) 
-                    && (exclude_end == "" || !file_name.ends_with(exclude_end))
+            // Files with non UTF-8 names can't be the ones we are looking for
+            let file_name = match entry.file_name().into_string() {
+                Ok(file_name) => file_name,
+                Err(_) => continue
+            };
+            if file_name.starts_with(starts_with)
+                    && (exclude_end.is_empty()
+                        || !file_name.ends_with(exclude_end))
             {
                 paths.push(entry.path());
             }
@@ -32,64 +103,1039 @@ fn find_files(starts_with: &str, exclude_end: &str) -> Result<Vec<PathBuf>> {
     Ok(paths)
 }
 
-#[derive(Parser, Debug)]
+/// Resampling filters exposed on the cli
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Filter {
+    Nearest,
+    Triangle,
+    CatmullRom,
+    Gaussian,
+    Lanczos3,
+}
+
+impl From<Filter> for FilterType {
+    fn from(filter: Filter) -> Self {
+        match filter {
+            Filter::Nearest => FilterType::Nearest,
+            Filter::Triangle => FilterType::Triangle,
+            Filter::CatmullRom => FilterType::CatmullRom,
+            Filter::Gaussian => FilterType::Gaussian,
+            Filter::Lanczos3 => FilterType::Lanczos3,
+        }
+    }
+}
+
+/// How the ink is told apart from the paper before keying it
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Binarization {
+    None,
+    Otsu,
+    Sauvola,
+}
+
+/// What to do with a region missing its second corner when closing
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Incomplete {
+    Drop,
+    Warn,
+    Prompt,
+}
+
+impl From<Incomplete> for IncompletePolicy {
+    fn from(incomplete: Incomplete) -> Self {
+        match incomplete {
+            Incomplete::Drop => IncompletePolicy::Drop,
+            Incomplete::Warn => IncompletePolicy::Warn,
+            Incomplete::Prompt => IncompletePolicy::Prompt,
+        }
+    }
+}
+
+/// Output formats of the crops
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Format {
+    Png,
+    Tiff,
+}
+
+impl Format {
+    fn extension(self) -> &'static str {
+        match self {
+            Format::Png => "png",
+            Format::Tiff => "tiff",
+        }
+    }
+}
+
+#[derive(Parser, Debug, Clone)]
 #[command(author, version, about, long_about = None)]
+#[command(subcommand_negates_reqs = true, args_conflicts_with_subcommands = true)]
 struct Args {
-    input_pdf: PathBuf,
+    #[arg(required = true)]
+    input_pdf: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: Option<Commands>,
 
-    #[arg(short, long, default_value_t = String::from("out"))]
+    #[arg(short, long, global = true, default_value_t = String::from("out"))]
     out_dir: String,
+
+    /// What to do with a region missing its second corner when the window is
+    /// closed
+    #[arg(long, value_enum, default_value_t = Incomplete::Prompt)]
+    incomplete: Incomplete,
+
+    /// Minimum size of the sides of a region in pixels, smaller regions are
+    /// discarded
+    #[arg(long, global = true, default_value_t = DEFAULT_MIN_SIZE)]
+    min_size: f32,
+
+    /// Scale factor applied to the crops before saving them
+    #[arg(long, global = true, conflicts_with = "dpi")]
+    scale: Option<f32>,
+
+    /// Resample the crops to this density, in dots per inch
+    #[arg(long, global = true)]
+    dpi: Option<f32>,
+
+    /// Density of the images of the pdf when `pdfimages` can't tell it, in
+    /// dots per inch, without it their crops aren't resampled by `--dpi`
+    #[arg(long, global = true)]
+    source_dpi: Option<f32>,
+
+    /// Filter used to resample the crops
+    #[arg(long, global = true, value_enum, default_value_t = Filter::Lanczos3)]
+    filter: Filter,
+
+    /// Image format of the crops
+    #[arg(long, global = true, value_enum, default_value_t = Format::Png)]
+    format: Format,
+
+    /// Also lay out every crop on a contact sheet, saved as PDF or as an
+    /// image depending on the extension
+    #[arg(long, global = true)]
+    sheet: Option<PathBuf>,
+
+    /// Columns of the contact sheet grid, when 0 the crops flow keeping
+    /// their size
+    #[arg(long, global = true, default_value_t = 3)]
+    sheet_columns: u32,
+
+    /// Margin around the contact sheet, in pixels at 150 DPI
+    #[arg(long, global = true, default_value_t = 60)]
+    sheet_margin: u32,
+
+    /// Split the contact sheet across A4 pages
+    #[arg(long, global = true)]
+    sheet_pages: bool,
+
+    /// File with the color palettes offered on the window, by default
+    /// `~/.config/plotview/palettes` is used if it exists
+    #[arg(long)]
+    palettes: Option<PathBuf>,
+
+    /// Color the new regions in order from a scheme, `okabe-ito`, `viridis`,
+    /// `tableau10` or the name of a palette
+    #[arg(long)]
+    colors: Option<String>,
+
+    /// Draw the rectangles with this ratio of width to height, like `4:3`
+    #[arg(long, value_parser = parse_aspect, conflicts_with = "size")]
+    aspect: Option<f32>,
+
+    /// Draw the rectangles with this size in pixels, like `800x600`
+    #[arg(long, value_parser = parse_size)]
+    size: Option<(f32, f32)>,
+
+    /// Start with the regions of a template, by name from
+    /// `~/.config/plotview/templates` or by path if it contains a `/`
+    #[arg(long, global = true)]
+    template: Option<String>,
+
+    /// Align the template to the page first, finding how the page is
+    /// shifted, scaled and rotated from the page the template was saved on
+    #[arg(long, global = true, requires = "template")]
+    align: bool,
+
+    /// Fit every rectangle of the template to the ink found inside it
+    /// extended by this margin in pixels, to absorb small layout shifts
+    #[arg(long, global = true, requires = "template")]
+    fit_content: Option<f32>,
+
+    /// Save the regions drawn as a template with this name, or on this path
+    /// if it contains a `/`
+    #[arg(long)]
+    save_template: Option<String>,
+
+    /// Crop the regions of the template without opening the window, like
+    /// the session saved on `.plotview/session` of the output directory
+    #[arg(long, requires = "template")]
+    no_window: bool,
+
+    /// Keep watching the input pdf, the window shows its new versions while
+    /// open and once closed every change extracts the crops again with the
+    /// regions of the session
+    #[arg(long)]
+    watch: bool,
+
+    /// Open an image of this page of the pdf
+    #[arg(long, global = true, conflicts_with = "pages")]
+    page: Option<u32>,
+
+    /// Only extract the images of these pages, like `3-7,12`
+    #[arg(long, global = true, value_delimiter = ',',
+        value_parser = parse_page_range)]
+    pages: Vec<PageRange>,
+
+    /// Open the image with this number, as listed by `--list-images`, even
+    /// if it's a mask or a small one
+    #[arg(long, global = true)]
+    image: Option<u32>,
+
+    /// Smallest side in pixels of the images offered, smaller ones and masks
+    /// are skipped unless chosen with `--image`
+    #[arg(long, global = true, default_value_t = MIN_IMAGE_SIZE)]
+    min_image_size: u32,
+
+    /// List the images of the pdf and exit
+    #[arg(long)]
+    list_images: bool,
+
+    /// Open every picture of the pdf, or of the pages given, to go through
+    /// them with the strip at the bottom of the window or PageUp and
+    /// PageDown, the crops of each one are saved on a directory named after
+    /// it
+    #[arg(long, conflicts_with_all = ["image", "watch"])]
+    all_images: bool,
+
+    /// Keep the images extracted by `pdfimages` on their temporary directory
+    /// instead of removing them, to debug
+    #[arg(long, global = true)]
+    keep_temp: bool,
+
+    /// Make the paper of scanned pages white before keying the ink
+    #[arg(long, global = true)]
+    white_paper: bool,
+
+    /// Turn everything that isn't ink white before keying it, with one
+    /// threshold for the whole page or one around every pixel
+    #[arg(long, global = true, value_enum, default_value_t = Binarization::None)]
+    binarize: Binarization,
+
+    /// Side of the window around every pixel of the `sauvola` threshold, in
+    /// pixels
+    #[arg(long, global = true, default_value_t = 31)]
+    sauvola_window: u32,
+
+    /// How much darker than its surroundings the ink is with the `sauvola`
+    /// threshold, from 0 to 1
+    #[arg(long, global = true, default_value_t = 0.2)]
+    sauvola_k: f32,
+
+    /// Remove the specks of ink smaller than this many pixels
+    #[arg(long, global = true, default_value_t = 0)]
+    despeckle: u32,
 }
 
-fn main() -> Result<()> {
-    // Parse the args and check that are valid
-    let args = Args::parse();
-    if !args.input_pdf.exists() {
-        bail!("Input PDF doesn't exist");
+/// Cleanup of the pages asked with the args
+fn preprocess(args: &Args) -> Preprocess {
+    Preprocess {
+        normalize_paper: args.white_paper,
+        binarize: match args.binarize {
+            Binarization::None => Binarize::None,
+            Binarization::Otsu => Binarize::Otsu,
+            Binarization::Sauvola => Binarize::Sauvola {
+                window: args.sauvola_window,
+                k: args.sauvola_k
+            },
+        },
+        despeckle: args.despeckle
     }
+}
 
-    // Check if pdfimages exists
-    Command::new("pdfimages").args(&["--help"]).output()
-        .map_err(|_| anyhow!("`pdfimages` not present in the path"))?;
+#[derive(Subcommand, Debug, Clone)]
+enum Commands {
+    /// Crop many pdfs at once with the regions of a template, without
+    /// opening the window, each one on a directory of the output directory
+    /// named after it
+    Batch(Batch),
+}
+
+#[derive(clap::Args, Debug, Clone)]
+struct Batch {
+    /// Pdfs to crop
+    #[arg(required = true)]
+    inputs: Vec<PathBuf>,
+
+    /// Pdfs cropped at the same time, by default one per core
+    #[arg(short, long)]
+    jobs: Option<usize>,
+}
+
+/// Parse an aspect ratio as `width:height` or as a single number
+fn parse_aspect(aspect: &str) -> Result<f32> {
+    let aspect = match aspect.split_once(':') {
+        Some((width, height)) => {
+            width.trim().parse::<f32>()? / height.trim().parse::<f32>()?
+        }
+        None => aspect.trim().parse()?
+    };
+    if !aspect.is_finite() || aspect <= 0.0 {
+        bail!("The aspect ratio must be positive");
+    }
+
+    Ok(aspect)
+}
+
+/// Parse a range of pages as `first-last` or a single page
+fn parse_page_range(range: &str) -> Result<PageRange> {
+    let (first, last) = match range.split_once('-') {
+        Some((first, last)) => (first.trim().parse()?, last.trim().parse()?),
+        None => {
+            let page = range.trim().parse()?;
+            (page, page)
+        }
+    };
+    if first == 0 || last < first {
+        bail!("Expected pages counted from 1 like `3-7`");
+    }
+
+    Ok(PageRange::new(first, last))
+}
+
+/// Parse a size as `widthxheight`
+fn parse_size(size: &str) -> Result<(f32, f32)> {
+    let (width, height) = size.split_once('x')
+        .ok_or_else(|| anyhow!("Expected a size like `800x600`"))?;
+    let (width, height) = (width.trim().parse::<f32>()?,
+        height.trim().parse::<f32>()?);
+    if width < 1.0 || height < 1.0 {
+        bail!("The size must be at least 1x1");
+    }
+
+    Ok((width, height))
+}
+
+/// Pages whose images are extracted, all if empty, sorted and merged so
+/// every page is extracted once
+fn pages(args: &Args) -> Vec<PageRange> {
+    match args.page {
+        Some(page) => vec![PageRange::new(page, page)],
+        None => PageRange::merge(&args.pages),
+    }
+}
+
+/// If the images of `page` are extracted
+fn wanted_page(args: &Args, page: u32) -> bool {
+    let pages = pages(args);
+    pages.is_empty() || pages.iter().any(|range| range.contains(page))
+}
 
-    // Produce the images for the input file
-    let result = Command::new("pdfimages")
-            .args(&[args.input_pdf.to_str().unwrap(), "img"]).output()?;
-    if !result.status.success() {
-        bail!("`pdfimages` command failed: {}", 
-            std::str::from_utf8(&result.stderr).unwrap());
+/// Print the images of the pdf, marking the ones skipped by default
+fn list_images(args: &Args, infos: &[ImageInfo]) {
+    println!("{:>5} {:>5}  {:<7} {:>6} {:>6} {:>6} {:>6}",
+        "num", "page", "type", "width", "height", "x-ppi", "y-ppi");
+    for info in infos.iter().filter(|info| wanted_page(args, info.page)) {
+        println!("{:>5} {:>5}  {:<7} {:>6} {:>6} {:>6} {:>6}{}",
+            info.num, info.page, info.kind, info.width, info.height,
+            info.x_ppi, info.y_ppi,
+            if info.is_picture(args.min_image_size) { "" } else { "  skipped" });
+    }
+}
+
+/// Error of a pdf without pictures on the pages asked
+fn no_pictures(args: &Args) -> anyhow::Error {
+    anyhow!("The pdf has no image of at least {0}x{0} pixels{1}, see them \
+        with `--list-images`", args.min_image_size,
+        match pages(args).as_slice() {
+            [] => String::new(),
+            pages => format!(" on the pages {}", pages.iter()
+                .map(PageRange::to_string)
+                .collect::<Vec<_>>()
+                .join(",")),
+        })
+}
+
+/// Number of the image of the pdf to open, the one given with `--image`, or
+/// the only picture of the pages given with `--page` or `--pages` or of the
+/// whole pdf, when there are several the user picks one if `ask` or else the
+/// largest
+fn choose_image(args: &Args, infos: &[ImageInfo], ask: bool) -> Result<u32> {
+    if let Some(num) = args.image {
+        return Ok(num);
+    }
+
+    let mut candidates: Vec<&ImageInfo> = infos.iter()
+        .filter(|info| info.is_picture(args.min_image_size))
+        .filter(|info| wanted_page(args, info.page))
+        .collect();
+    candidates.sort_by_key(|info| Reverse(info.width as u64 * info.height as u64));
+    if candidates.len() <= 1 || !ask {
+        return candidates.first()
+            .map(|info| info.num)
+            .ok_or_else(|| no_pictures(args));
+    }
+
+    println!("The pdf has {} images:", candidates.len());
+    for (idx, info) in candidates.iter().enumerate() {
+        println!("  [{}] image {} on page {}, {}x{}",
+            idx + 1, info.num, info.page, info.width, info.height);
+    }
+    loop {
+        print!("Image to open [1]: ");
+        std::io::stdout().flush()?;
+        let mut answer = String::new();
+        if std::io::stdin().lock().read_line(&mut answer)? == 0 {
+            bail!("No image chosen");
+        }
+        let answer = answer.trim();
+        if answer.is_empty() {
+            return Ok(candidates[0].num);
+        }
+        match answer.parse::<usize>() {
+            Ok(idx) if idx >= 1 && idx <= candidates.len() => {
+                return Ok(candidates[idx - 1].num);
+            }
+            _ => println!("Type a number from 1 to {}", candidates.len()),
+        }
+    }
+}
+
+/// Extract the chosen image of the pdf, see [`choose_image`], with
+/// `pdfimages` on a temporary directory and load it, with the density it
+/// has on the page, the directory is removed unless `--keep-temp`
+///
+/// Returns the image and its number on the pdf, unknown if the images
+/// couldn't be listed.
+fn extract_image(
+    args: &Args,
+    input_pdf: &Path,
+    ask: bool
+) -> Result<(Image, Option<u32>)> {
+    // Without the list of images fall back to the first one
+    let infos = pdfimages::list(input_pdf).unwrap_or_else(|err| {
+        eprintln!("Couldn't list the images of the pdf: {:#}", err);
+        Vec::new()
+    });
+    let num = if infos.is_empty() {
+        args.image
+    } else {
+        Some(choose_image(args, &infos, ask)?)
     };
 
-    let mut crops = Vec::new();
+    // Only the page of the image is needed, the crops are named after it
+    let pages = match infos.iter().find(|info| Some(info.num) == num) {
+        Some(info) => vec![PageRange::new(info.page, info.page)],
+        None => pages(args),
+    };
+    let dir = WorkDir::new(args.keep_temp)?;
+    pdfimages::extract(input_pdf, &dir.path.join("img"), &pages)?;
 
-    // Transform the generated images to bmp and save it
-    let mut path = find_files("img-", "bmp")?[0].clone();
+    let mut paths = find_files(&dir.path, "img-", "bmp")?;
+    paths.sort();
 
-    // Load the image
-    let image = Image::new(path)?;
+    Ok((load_image(args, &paths, &infos, num)?, num))
+}
 
-    // Start the image cropper
-    crops = ImageCropper::new(image)
-        .run()?;
+/// Extract every picture of the pdf on the pages asked and load them in
+/// order, the images skipped are the ones [`choose_image`] doesn't offer
+fn extract_pictures(args: &Args, input_pdf: &Path) -> Result<Vec<Image>> {
+    let infos = pdfimages::list(input_pdf)?;
+    let nums: Vec<u32> = infos.iter()
+        .filter(|info| info.is_picture(args.min_image_size))
+        .filter(|info| wanted_page(args, info.page))
+        .map(|info| info.num)
+        .collect();
+    if nums.is_empty() {
+        return Err(no_pictures(args));
+    }
 
-    let out_dir = PathBuf::from(args.out_dir);
+    let dir = WorkDir::new(args.keep_temp)?;
+    pdfimages::extract(input_pdf, &dir.path.join("img"), &pages(args))?;
+    let mut paths = find_files(&dir.path, "img-", "bmp")?;
+    paths.sort();
+
+    nums.into_iter()
+        .map(|num| load_image(args, &paths, &infos, Some(num)))
+        .collect()
+}
+
+/// Load the image with number `num` out of the images extracted on `paths`,
+/// or the first one, with the density it has on the page or else the one of
+/// `--source-dpi`
+///
+/// Without the list of images the files are expected to be numbered from
+/// the first page of the pdf.
+fn load_image(
+    args: &Args,
+    paths: &[PathBuf],
+    infos: &[ImageInfo],
+    num: Option<u32>
+) -> Result<Image> {
+    let path = match num {
+        Some(num) if infos.is_empty() => paths.iter()
+            .find(|path| pdfimages::image_num(path) == Some(num))
+            .ok_or_else(|| anyhow!("The pdf doesn't have the image {}", num))?,
+        Some(num) => pdfimages::find_image(paths, infos, num)
+            .ok_or_else(|| anyhow!("The pdf doesn't have the image {}", num))?,
+        None => paths.first()
+            .ok_or_else(|| anyhow!("The pdf doesn't have any image"))?,
+    };
+    let mut image = Image::new(path)?;
+    image.set_dpi(infos.iter()
+        .find(|info| Some(info.num) == num)
+        .map(|info| (info.x_ppi, info.y_ppi))
+        .or(args.source_dpi.map(|dpi| (dpi, dpi))));
+
+    // Told before the regions are drawn rather than failing once saving
+    if args.dpi.is_some() && image.dpi().is_none() {
+        eprintln!("The density of {:?} is unknown, its crops will keep their \
+            resolution, set it with `--source-dpi`", path);
+    }
+
+    Ok(image)
+}
+
+/// Regions of the template on `path` placed over `image`, aligned to it and
+/// fitted to its content if asked, `verbose` tells how it was aligned
+fn template_regions(
+    args: &Args,
+    path: &Path,
+    image: &Image,
+    verbose: bool
+) -> Result<Regions> {
+    let mut template = Template::load(path)
+        .with_context(|| format!("Couldn't load the template {:?}", path))?;
+    if args.align {
+        let alignment = template.align(image);
+        if verbose {
+            match alignment {
+                Some(alignment) => eprintln!("Template aligned: shifted \
+                    ({:.3}, {:.3}), scaled {:.3}, rotated {:.2}°, score {:.2}",
+                    alignment.dx, alignment.dy, alignment.scale,
+                    alignment.angle, alignment.score),
+                None => eprintln!("The template {:?} has no reference page, \
+                    it can't be aligned", path),
+            }
+        }
+    }
+
+    let mut regions = Regions::new();
+    regions.set_min_size(args.min_size);
+    for mut region in template.regions(image.dimensions()) {
+        if let Some(margin) = args.fit_content {
+            region.fit_to_content(image, margin);
+        }
+        regions.push(region);
+    }
+
+    Ok(regions)
+}
+
+/// Resample and save the crops on `out_dir`, with the session to extract
+/// them again when watching or cropping without the window and the template
+/// if asked
+///
+/// `page` is the image the crops come from, without its density `--dpi`
+/// is ignored. The previous contents of `out_dir` are only removed once
+/// every crop is resampled.
+///
+/// Returns the saved crops, to lay them out on a contact sheet.
+fn save_crops(
+    args: &Args,
+    out_dir: &Path,
+    crops: Vec<Crop>,
+    page: &Image
+) -> Result<Vec<Image>> {
+    // The regions, with a copy of the page to align the next ones to, only
+    // when they are saved
+    let save_session = args.watch || args.no_window;
+    let template = (save_session || args.save_template.is_some()).then(|| {
+        Template::new(
+            SESSION_FILE,
+            crops.iter().map(|crop| &crop.region),
+            page.dimensions()
+        ).with_reference(page)
+    });
+
+    // Resample the crops and set their output format
+    let resample = match (args.scale, args.dpi, page.dpi()) {
+        (Some(scale), _, _) => Resample::Scale(scale),
+        (_, Some(dpi), Some(_)) => Resample::Dpi(dpi),
+        _ => Resample::None,
+    };
+    let mut crops: Vec<Image> = crops.into_iter()
+        .map(|crop| crop.image)
+        .collect();
+    for crop in &mut crops {
+        crop.resample(resample, args.filter.into())?;
+        crop.set_extension(args.format.extension());
+    }
 
     // Clear the out_dir
     if out_dir.exists() {
-        std::fs::remove_dir_all(&out_dir)?;
+        std::fs::remove_dir_all(out_dir)?;
+    }
+    std::fs::create_dir_all(out_dir)?;
+
+    // Save the regions
+    if let Some(template) = &template {
+        if save_session {
+            template.save(&session_path(out_dir))
+                .context("Couldn't save the session")?;
+        }
+        if let Some(name) = &args.save_template {
+            let path = Template::path(name)
+                .ok_or_else(|| anyhow!("Unknown config directory"))?;
+            template.save(&path)
+                .with_context(|| format!("Couldn't save the template {:?}", path))?;
+        }
     }
-    std::fs::create_dir(&out_dir)?;
 
     // Save the image crops
     for crop in &crops {
-        crop.save(&out_dir)?;
+        crop.save(out_dir)?;
+    }
+
+    Ok(crops)
+}
+
+/// Lay out `crops` on a contact sheet saved on `path`
+fn save_sheet(args: &Args, path: &Path, crops: &[Image]) -> Result<()> {
+    let mut sheet = if args.sheet_pages {
+        ContactSheet::a4_pages()
+    } else {
+        ContactSheet::default()
+    };
+    sheet.margin = args.sheet_margin;
+    if args.sheet_columns == 0 {
+        sheet.layout = Layout::Flow;
+    } else {
+        sheet.layout = Layout::Grid { columns: args.sheet_columns };
+    }
+    sheet.save(crops, path)
+        .with_context(|| format!("Couldn't save the contact sheet {:?}", path))
+}
+
+/// Session of the crops saved on `out_dir`
+fn session_path(out_dir: &Path) -> PathBuf {
+    out_dir.join(STATE_DIR).join(SESSION_FILE)
+}
+
+/// Extract the crops again with the regions of the session whenever the
+/// input pdf changes, till the program is stopped
+fn watch(args: &Args, input_pdf: &Path) -> ! {
+    let out_dir = PathBuf::from(&args.out_dir);
+    let session = session_path(&out_dir);
+    let mut watcher = Watcher::new(input_pdf);
+    eprintln!("Watching {:?}, press Ctrl+C to stop", watcher.path());
+    loop {
+        watcher.wait();
+        eprintln!("{:?} changed, extracting the crops again", watcher.path());
+        let saved = extract_image(args, input_pdf, false).and_then(|(image, _)| {
+            let image = preprocess(args).apply(&image);
+            let mut regions = template_regions(args, &session, &image, true)?;
+            regions.validate();
+            let crops = regions.get_image_crops(&image)?;
+            let crops = save_crops(args, &out_dir, crops, &image)?;
+            if let Some(sheet) = &args.sheet {
+                save_sheet(args, sheet, &crops)?;
+            }
+
+            Ok(crops.len())
+        });
+        match saved {
+            Ok(count) => eprintln!("Saved {} crops", count),
+            Err(err) => eprintln!("{:#}", err),
+        }
+    }
+}
+
+/// Progress bar of a batch, printed on stderr
+struct Progress {
+    total: usize,
+    done: Mutex<usize>,
+
+    /// If stderr is a terminal, the bar is only drawn on one
+    terminal: bool,
+}
+
+impl Progress {
+    fn new(total: usize) -> Self {
+        let progress = Self {
+            total,
+            done: Mutex::new(0),
+            terminal: std::io::stderr().is_terminal()
+        };
+        progress.draw(0, "");
+
+        progress
+    }
+
+    /// Count `input` as done
+    fn tick(&self, input: &Path) {
+        let mut done = self.done.lock().unwrap();
+        *done += 1;
+        self.draw(*done, &input.display().to_string());
     }
 
-    // Cleanup, generated bmps
-    for path in &find_files("img-", "bmp")? {
-        std::fs::remove_file(path)
-            .context("Can't remove files for cleanup")?;
+    fn draw(&self, done: usize, last: &str) {
+        if !self.terminal {
+            return;
+        }
+        let filled = PROGRESS_WIDTH * done / self.total.max(1);
+        eprint!("\r\x1b[K[{}{}] {}/{} {}",
+            "#".repeat(filled), "-".repeat(PROGRESS_WIDTH - filled),
+            done, self.total, last);
+    }
+
+    fn finish(&self) {
+        if self.terminal {
+            eprintln!();
+        }
+    }
+}
+
+/// Crop `input_pdf` with the regions of the template on `template` and save
+/// the crops on `out_dir`
+///
+/// Returns the number of crops saved.
+fn crop_pdf(
+    args: &Args,
+    template: &Path,
+    input_pdf: &Path,
+    out_dir: &Path
+) -> Result<usize> {
+    let (image, _) = extract_image(args, input_pdf, false)?;
+    let image = preprocess(args).apply(&image);
+    let mut regions = template_regions(args, template, &image, false)?;
+    regions.validate();
+    let crops = regions.get_image_crops(&image)?;
+
+    let crops = save_crops(args, out_dir, crops, &image)?;
+    if let Some(sheet) = sheet_in(args, out_dir) {
+        save_sheet(args, &sheet, &crops)?;
+    }
+
+    Ok(crops.len())
+}
+
+/// Contact sheet on `out_dir`, with the file name of the one asked
+fn sheet_in(args: &Args, out_dir: &Path) -> Option<PathBuf> {
+    args.sheet.as_ref()
+        .and_then(|sheet| sheet.file_name())
+        .map(|name| out_dir.join(name))
+}
+
+/// Crop every pdf of the batch on a pool of threads, a pdf that fails
+/// doesn't stop the others, and report how each one went
+fn batch(args: &Args, batch: &Batch) -> Result<()> {
+    let name = args.template.as_ref()
+        .ok_or_else(|| anyhow!("The batch needs the regions of a `--template`"))?;
+    let template = Template::find(name)
+        .ok_or_else(|| anyhow!("Unknown template `{}`", name))?;
+
+    // Warn once instead of on every pdf, the alignments aren't told to keep
+    // the progress bar clean
+    let has_reference = Template::load(&template)
+        .with_context(|| format!("Couldn't load the template {:?}", template))?
+        .has_reference();
+    if args.align && !has_reference {
+        eprintln!("The template {:?} has no reference page, it can't be \
+            aligned", template);
+    }
+
+    // Every pdf on a directory named after it, numbered if repeated
+    let out_dir = PathBuf::from(&args.out_dir);
+    let mut names = HashSet::new();
+    let jobs: Vec<(&PathBuf, PathBuf)> = batch.inputs.iter()
+        .map(|input| {
+            let stem = input.file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_else(|| "pdf".to_owned());
+            let mut name = stem.clone();
+            let mut count = 1;
+            while !names.insert(name.clone()) {
+                count += 1;
+                name = format!("{}-{}", stem, count);
+            }
+            (input, out_dir.join(name))
+        })
+        .collect();
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(batch.jobs.unwrap_or(0))
+        .build()?;
+    let start = Instant::now();
+    let progress = Progress::new(jobs.len());
+    let outcomes: Vec<(&Path, Result<usize>, Duration)> = pool.install(|| {
+        jobs.par_iter()
+            .map(|(input, dir)| {
+                let start = Instant::now();
+                let result = crop_pdf(args, &template, input, dir);
+                progress.tick(input);
+                (input.as_path(), result, start.elapsed())
+            })
+            .collect()
+    });
+    progress.finish();
+
+    let report = batch_report(&outcomes, start.elapsed());
+    std::fs::create_dir_all(&out_dir)?;
+    std::fs::write(out_dir.join(REPORT_FILE), &report.lines)?;
+
+    print!("{}", report.summary);
+    if report.failed > 0 {
+        bail!("{} of {} pdfs failed, see {:?}", report.failed, outcomes.len(),
+            out_dir.join(REPORT_FILE));
     }
 
     Ok(())
 }
+
+/// How a batch went
+struct Report {
+    /// The outcome of every pdf, one per line with tab separated fields
+    lines: String,
+
+    /// Counts and errors, for the user
+    summary: String,
+
+    /// Number of pdfs that failed
+    failed: usize,
+}
+
+/// Report of a batch that took `elapsed` from the outcome of every pdf, with
+/// the number of crops saved and how long it took
+fn batch_report(
+    outcomes: &[(&Path, Result<usize>, Duration)],
+    elapsed: Duration
+) -> Report {
+    let mut lines = String::new();
+    let mut errors = String::new();
+    let mut crops = 0;
+    let mut failed = 0;
+    for (input, result, elapsed) in outcomes {
+        match result {
+            Ok(count) => {
+                crops += count;
+                lines.push_str(&format!("ok\t{}\t{} crops\t{:.2}s\n",
+                    input.display(), count, elapsed.as_secs_f32()));
+            }
+            Err(err) => {
+                failed += 1;
+                lines.push_str(&format!("failed\t{}\t{:#}\t{:.2}s\n",
+                    input.display(), err, elapsed.as_secs_f32()));
+                errors.push_str(&format!("  {}: {:#}\n", input.display(), err));
+            }
+        }
+    }
+
+    let summary = format!("Cropped {} of {} pdfs, {} crops in {:.1}s\n{}",
+        outcomes.len() - failed, outcomes.len(), crops,
+        elapsed.as_secs_f32(), errors);
+
+    Report { lines, summary, failed }
+}
+
+fn main() -> Result<()> {
+    // Parse the args and check that are valid
+    let mut args = Args::parse();
+
+    // Check if pdfimages exists
+    Command::new("pdfimages").args(["--help"]).output()
+        .map_err(|_| anyhow!("`pdfimages` not present in the path"))?;
+
+    let input_pdf = match (&args.command, &args.input_pdf) {
+        (Some(Commands::Batch(options)), _) => return batch(&args, options),
+        (None, Some(input_pdf)) => input_pdf.clone(),
+        (None, None) => bail!("Missing the input PDF"),
+    };
+    if !input_pdf.exists() {
+        bail!("Input PDF doesn't exist");
+    }
+
+    if args.list_images {
+        list_images(&args, &pdfimages::list(&input_pdf)?);
+        return Ok(());
+    }
+
+    // Produce the images for the input file, its new versions while watching
+    // are the same image of the pdf
+    let images = if args.all_images {
+        extract_pictures(&args, &input_pdf)?
+    } else {
+        let ask = !args.no_window && std::io::stdin().is_terminal();
+        let (image, num) = extract_image(&args, &input_pdf, ask)?;
+        args.image = args.image.or(num);
+        vec![image]
+    };
+
+    // Load the palettes, a missing default file just means there are none
+    let palettes = match (&args.palettes, Palette::default_path()) {
+        (Some(path), _) => Palette::load(path)?,
+        (None, Some(path)) if path.exists() => {
+            Palette::load(&path).unwrap_or_else(|err| {
+                eprintln!("Couldn't load the palettes: {:#}", err);
+                Vec::new()
+            })
+        }
+        _ => Vec::new()
+    };
+
+    let scheme = args.colors.as_deref()
+        .map(|name| ColorScheme::from_name(name, &palettes)
+            .ok_or_else(|| anyhow!("Unknown color scheme `{}`", name)))
+        .transpose()?;
+    // Without the window the pages are cropped cleaned up, on it the cleanup
+    // can still be changed
+    let preprocess = preprocess(&args);
+    let images: Vec<Image> = if args.no_window {
+        images.iter().map(|image| preprocess.apply(image)).collect()
+    } else {
+        images
+    };
+
+    let template = args.template.as_deref()
+        .map(|name| Template::find(name)
+            .ok_or_else(|| anyhow!("Unknown template `{}`", name)))
+        .transpose()?;
+
+    // Start every page with the regions of the template if any
+    let pages = images.into_iter()
+        .map(|image| {
+            let mut regions = match &template {
+                Some(path) => template_regions(&args, path, &image, true)?,
+                None => Regions::new()
+            };
+            regions.set_min_size(args.min_size);
+            regions.set_scheme(scheme.clone());
+            match (args.aspect, args.size) {
+                (Some(aspect), _) => {
+                    regions.set_constraint(Constraint::Aspect(aspect));
+                }
+                (_, Some((width, height))) => {
+                    regions.set_constraint(Constraint::Size(width, height));
+                }
+                _ => {}
+            }
+            Ok((image, regions))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let pages = if args.no_window {
+        pages.into_iter()
+            .map(|(image, mut regions)| {
+                regions.validate();
+                let crops = regions.get_image_crops(&image)?;
+                Ok((image, crops))
+            })
+            .collect::<Result<Vec<_>>>()?
+    } else {
+        let mut cropper = ImageCropper::with_pages(pages)?
+            .incomplete_policy(args.incomplete.into())
+            .palettes(palettes)
+            .preprocess(preprocess);
+        if args.watch {
+            let reload_pdf = input_pdf.clone();
+            let reload_args = args.clone();
+            cropper = cropper.watch(&input_pdf, move || {
+                extract_image(&reload_args, &reload_pdf, false)
+                    .map(|(image, _)| image)
+                    .map_err(|err| eprintln!("Couldn't reload the pdf: {:#}", err))
+                    .ok()
+            });
+        }
+        cropper.run_pages()?
+    };
+
+    // Every page on a directory named after it when there are several, with
+    // the crops of all of them on a single contact sheet
+    let out_dir = PathBuf::from(&args.out_dir);
+    let several = pages.len() > 1;
+    let mut saved = Vec::new();
+    for (page, crops) in pages {
+        let dir = if several {
+            out_dir.join(page.name())
+        } else {
+            out_dir.clone()
+        };
+        saved.extend(save_crops(&args, &dir, crops, &page)?);
+    }
+    if let Some(sheet) = &args.sheet {
+        save_sheet(&args, sheet, &saved)?;
+    }
+
+    if args.watch {
+        watch(&args, &input_pdf);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_page_range_reads_pages_and_ranges() {
+        assert_eq!(parse_page_range("3").unwrap(), PageRange::new(3, 3));
+        assert_eq!(parse_page_range(" 3 - 7 ").unwrap(), PageRange::new(3, 7));
+        assert!(parse_page_range("0").is_err());
+        assert!(parse_page_range("0-2").is_err());
+        assert!(parse_page_range("7-3").is_err());
+        assert!(parse_page_range("3-").is_err());
+        assert!(parse_page_range("a").is_err());
+    }
+
+    #[test]
+    fn pages_splits_the_list_of_ranges() {
+        let args = Args::try_parse_from(["plotview", "a.pdf", "--pages", "3-7,12"])
+            .unwrap();
+        assert_eq!(pages(&args), [PageRange::new(3, 7), PageRange::new(12, 12)]);
+        assert!(wanted_page(&args, 5));
+        assert!(wanted_page(&args, 12));
+        assert!(!wanted_page(&args, 8));
+
+        let args = Args::try_parse_from(["plotview", "a.pdf", "--page", "2"])
+            .unwrap();
+        assert_eq!(pages(&args), [PageRange::new(2, 2)]);
+        assert!(Args::try_parse_from(["plotview", "a.pdf", "--pages", "7-3"])
+            .is_err());
+    }
+
+    #[test]
+    fn batch_report_lists_every_pdf() {
+        let outcomes = [
+            (Path::new("in/a.pdf"), Ok(3), Duration::from_millis(1500)),
+            (
+                Path::new("in/b.pdf"),
+                Err(anyhow!("No images").context("Couldn't extract")),
+                Duration::from_millis(250)
+            ),
+        ];
+
+        let report = batch_report(&outcomes, Duration::from_millis(1960));
+        assert_eq!(report.lines, concat!(
+            "ok\tin/a.pdf\t3 crops\t1.50s\n",
+            "failed\tin/b.pdf\tCouldn't extract: No images\t0.25s\n"
+        ));
+        assert_eq!(report.summary, concat!(
+            "Cropped 1 of 2 pdfs, 3 crops in 2.0s\n",
+            "  in/b.pdf: Couldn't extract: No images\n"
+        ));
+        assert_eq!(report.failed, 1);
+    }
+
+    #[test]
+    fn pages_merges_overlapping_ranges() {
+        let args = Args::try_parse_from(["plotview", "a.pdf", "--pages", "3-5,4-7"])
+            .unwrap();
+        assert_eq!(pages(&args), [PageRange::new(3, 7)]);
+
+        let args = Args::try_parse_from(
+            ["plotview", "a.pdf", "--pages", "12,3,3,8-9,10,1-2"]).unwrap();
+        assert_eq!(pages(&args), [PageRange::new(1, 3), PageRange::new(8, 10),
+            PageRange::new(12, 12)]);
+    }
+}
@@ -0,0 +1,123 @@
+use egui::{Align2, Color32, ColorImage, FontId, TextureFilter, TextureHandle};
+use image::imageops::{self, FilterType};
+use image::RgbaImage;
+
+use crate::image::Image;
+
+/// Longest side of the thumbnails, in points
+const THUMBNAIL_SIZE: u32 = 96;
+
+/// Radius of the badge with the number of regions of a page, in points
+const BADGE_RADIUS: f32 = 9.0;
+
+/// Strip along the bottom of the window with a thumbnail of every page open,
+/// badged with how many regions it has, to switch between them
+#[derive(Default)]
+pub(crate) struct PageStrip {
+    /// Name and downscaled pixels of every page, uploaded on the first frame
+    thumbnails: Vec<(String, RgbaImage)>,
+    textures: Vec<TextureHandle>,
+
+    /// Page shown
+    pub current: usize,
+
+    /// Regions of every page, the one shown is counted as it's edited
+    pub counts: Vec<usize>,
+
+    /// Page the user clicked on, taken by the cropper
+    pub request: Option<usize>,
+}
+
+impl PageStrip {
+    /// Strip of the `pages` with their number of regions, showing the first
+    pub fn new<'a>(pages: impl IntoIterator<Item = (&'a Image, usize)>) -> Self {
+        let (thumbnails, counts) = pages.into_iter()
+            .map(|(image, count)| {
+                let (width, height) = image.dimensions();
+                let ratio = THUMBNAIL_SIZE as f32 / width.max(height) as f32;
+                let thumbnail = imageops::resize(
+                    image.buffer(),
+                    ((width as f32 * ratio) as u32).max(1),
+                    ((height as f32 * ratio) as u32).max(1),
+                    FilterType::Triangle
+                );
+                ((image.name().to_owned(), thumbnail), count)
+            })
+            .unzip();
+
+        Self {
+            thumbnails,
+            textures: Vec::new(),
+            current: 0,
+            counts,
+            request: None
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.thumbnails.len()
+    }
+
+    /// Show the strip, `shown` is the number of regions of the page shown
+    pub fn show(&mut self, ctx: &egui::Context, shown: usize) {
+        if self.textures.is_empty() {
+            self.textures = self.thumbnails.iter()
+                .map(|(name, thumbnail)| ctx.load_texture(
+                    format!("page-{}", name),
+                    ColorImage::from_rgba_unmultiplied(
+                        [thumbnail.width() as usize, thumbnail.height() as usize],
+                        thumbnail.as_raw()
+                    ),
+                    TextureFilter::Linear
+                ))
+                .collect();
+        }
+
+        egui::TopBottomPanel::bottom("pages").show(ctx, |ui| {
+            egui::ScrollArea::horizontal().show(ui, |ui| {
+                ui.horizontal(|ui| {
+                    for (idx, texture) in self.textures.iter().enumerate() {
+                        let count = if idx == self.current {
+                            shown
+                        } else {
+                            self.counts[idx]
+                        };
+                        let name = &self.thumbnails[idx].0;
+                        let response = ui.vertical(|ui| {
+                            let response = ui.add(egui::ImageButton::new(
+                                    texture.id(),
+                                    texture.size_vec2())
+                                .selected(idx == self.current));
+                            ui.small(format!("{}", idx + 1));
+                            response
+                        }).inner;
+
+                        if count > 0 {
+                            let center = response.rect.right_top()
+                                + egui::vec2(-BADGE_RADIUS, BADGE_RADIUS);
+                            let painter = ui.painter();
+                            painter.circle_filled(
+                                center,
+                                BADGE_RADIUS,
+                                Color32::from_rgb(200, 60, 50)
+                            );
+                            painter.text(
+                                center,
+                                Align2::CENTER_CENTER,
+                                count.to_string(),
+                                FontId::proportional(11.0),
+                                Color32::WHITE
+                            );
+                        }
+
+                        let response = response.on_hover_text(format!(
+                            "{}, {} regions", name, count));
+                        if response.clicked() && idx != self.current {
+                            self.request = Some(idx);
+                        }
+                    }
+                });
+            });
+        });
+    }
+}
@@ -0,0 +1,186 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use egui::Color32;
+
+use crate::error::{Error, Result};
+
+/// A named list of colors to pick the color of the regions from
+#[derive(Debug, Clone, PartialEq)]
+pub struct Palette {
+    pub name: String,
+    pub colors: Vec<Color32>,
+}
+
+impl Palette {
+    pub fn new(name: impl Into<String>, colors: Vec<Color32>) -> Self {
+        Self {
+            name: name.into(),
+            colors
+        }
+    }
+
+    /// Load the palettes of a config file, one palette per line as a name
+    /// followed by its colors in hex, lines starting with `#` are comments
+    ///
+    /// ```text
+    /// # Colors of the company
+    /// corporate = #1f4e79, #c00000, #7f7f7f
+    /// ```
+    pub fn load(path: &Path) -> Result<Vec<Self>> {
+        let text = fs::read_to_string(path)?;
+        Self::parse(&text).map_err(|message| Error::Palette {
+            path: path.to_owned(),
+            message
+        })
+    }
+
+    /// Default location of the palettes config file,
+    /// `$XDG_CONFIG_HOME/plotview/palettes` or `~/.config/plotview/palettes`
+    pub fn default_path() -> Option<PathBuf> {
+        let config = std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME")
+                .map(|home| Path::new(&home).join(".config")))?;
+
+        Some(config.join("plotview").join("palettes"))
+    }
+
+    fn parse(text: &str) -> std::result::Result<Vec<Self>, String> {
+        let mut palettes = Vec::new();
+        for (idx, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (name, colors) = line.split_once('=')
+                .ok_or_else(|| format!("line {}: missing `=`", idx + 1))?;
+            let colors = colors.split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|color| !color.is_empty())
+                .map(|color| parse_hex(color).ok_or_else(|| {
+                    format!("line {}: invalid color `{}`", idx + 1, color)
+                }))
+                .collect::<std::result::Result<Vec<_>, _>>()?;
+            palettes.push(Self::new(name.trim(), colors));
+        }
+
+        Ok(palettes)
+    }
+}
+
+/// Parse a `#rrggbb` color, the `#` is optional
+pub fn parse_hex(hex: &str) -> Option<Color32> {
+    let hex = hex.trim().trim_start_matches('#');
+    if hex.len() != 6 || !hex.is_ascii() {
+        return None;
+    }
+    let channel = |idx: usize| u8::from_str_radix(&hex[idx..idx + 2], 16).ok();
+
+    Some(Color32::from_rgb(channel(0)?, channel(2)?, channel(4)?))
+}
+
+/// Format a color as `#rrggbb`, ignoring its alpha
+pub fn to_hex(color: Color32) -> String {
+    format!("#{:02x}{:02x}{:02x}", color.r(), color.g(), color.b())
+}
+
+/// Colors given in order to the regions, so the series of a figure are told
+/// apart consistently
+#[derive(Debug, Clone, PartialEq)]
+pub enum ColorScheme {
+    /// Okabe-Ito, distinguishable with every kind of color blindness
+    OkabeIto,
+
+    /// Evenly spaced samples of the Viridis colormap
+    Viridis,
+
+    /// Tableau 10, the default categorical colors of Tableau
+    Tableau10,
+
+    /// Colors of a palette, for example one loaded from the config file
+    Custom(Palette),
+}
+
+const OKABE_ITO: [Color32; 8] = [
+    Color32::from_rgb(0xe6, 0x9f, 0x00),
+    Color32::from_rgb(0x56, 0xb4, 0xe9),
+    Color32::from_rgb(0x00, 0x9e, 0x73),
+    Color32::from_rgb(0xf0, 0xe4, 0x42),
+    Color32::from_rgb(0x00, 0x72, 0xb2),
+    Color32::from_rgb(0xd5, 0x5e, 0x00),
+    Color32::from_rgb(0xcc, 0x79, 0xa7),
+    Color32::from_rgb(0x00, 0x00, 0x00),
+];
+
+const VIRIDIS: [Color32; 10] = [
+    Color32::from_rgb(0x44, 0x01, 0x54),
+    Color32::from_rgb(0x48, 0x28, 0x78),
+    Color32::from_rgb(0x3e, 0x49, 0x89),
+    Color32::from_rgb(0x31, 0x68, 0x8e),
+    Color32::from_rgb(0x26, 0x82, 0x8e),
+    Color32::from_rgb(0x1f, 0x9e, 0x89),
+    Color32::from_rgb(0x35, 0xb7, 0x79),
+    Color32::from_rgb(0x6e, 0xce, 0x58),
+    Color32::from_rgb(0xb5, 0xde, 0x2b),
+    Color32::from_rgb(0xfd, 0xe7, 0x25),
+];
+
+const TABLEAU_10: [Color32; 10] = [
+    Color32::from_rgb(0x4e, 0x79, 0xa7),
+    Color32::from_rgb(0xf2, 0x8e, 0x2b),
+    Color32::from_rgb(0xe1, 0x57, 0x59),
+    Color32::from_rgb(0x76, 0xb7, 0xb2),
+    Color32::from_rgb(0x59, 0xa1, 0x4f),
+    Color32::from_rgb(0xed, 0xc9, 0x48),
+    Color32::from_rgb(0xb0, 0x7a, 0xa1),
+    Color32::from_rgb(0xff, 0x9d, 0xa7),
+    Color32::from_rgb(0x9c, 0x75, 0x5f),
+    Color32::from_rgb(0xba, 0xb0, 0xac),
+];
+
+impl ColorScheme {
+    /// The schemes that don't require a palette
+    pub fn builtin() -> [Self; 3] {
+        [Self::OkabeIto, Self::Viridis, Self::Tableau10]
+    }
+
+    /// Find a scheme by name, the builtin ones are `okabe-ito`, `viridis` and
+    /// `tableau10`, any other name is looked up on `palettes`
+    pub fn from_name(name: &str, palettes: &[Palette]) -> Option<Self> {
+        Self::builtin()
+            .into_iter()
+            .find(|scheme| scheme.name().eq_ignore_ascii_case(name))
+            .or_else(|| palettes.iter()
+                .find(|palette| palette.name == name)
+                .map(|palette| Self::Custom(palette.clone())))
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            Self::OkabeIto => "okabe-ito",
+            Self::Viridis => "viridis",
+            Self::Tableau10 => "tableau10",
+            Self::Custom(palette) => &palette.name,
+        }
+    }
+
+    pub fn colors(&self) -> &[Color32] {
+        match self {
+            Self::OkabeIto => &OKABE_ITO,
+            Self::Viridis => &VIRIDIS,
+            Self::Tableau10 => &TABLEAU_10,
+            Self::Custom(palette) => &palette.colors,
+        }
+    }
+
+    /// Color of the `idx`th region, the colors repeat once all are used
+    pub fn color(&self, idx: usize) -> Color32 {
+        let colors = self.colors();
+        if colors.is_empty() {
+            return Color32::GRAY;
+        }
+
+        colors[idx % colors.len()]
+    }
+}
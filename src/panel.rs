@@ -0,0 +1,738 @@
+use egui::Color32;
+use egui::widgets::color_picker::{color_picker_color32, show_color, Alpha};
+
+use crate::image::Image;
+use crate::palette::{parse_hex, to_hex, ColorScheme, Palette};
+use crate::preprocess::{Binarize, Preprocess};
+use crate::regions::{
+    Align, Constraint, Overlay, Region, RegionState, Regions, DUPLICATE_OFFSET
+};
+use crate::snap::Snapping;
+
+/// Number of recently used colors remembered
+const MAX_RECENT_COLORS: usize = 12;
+
+/// Size of the color swatches, in points
+const SWATCH_SIZE: f32 = 16.0;
+
+/// What a left click on the image does
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tool {
+    /// Place the two corners of a rectangle
+    Rectangle,
+
+    /// Place the points of a polygon, closed by clicking its first point or
+    /// pressing enter
+    Polygon,
+
+    /// Select the region under the cursor
+    Select,
+
+    /// Sample the color of the image under the cursor
+    Eyedropper,
+}
+
+/// How the image is edited, changed from the panel and the keyboard
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Settings {
+    /// What a left click on the image does
+    pub tool: Tool,
+
+    /// What is drawn over the image
+    pub overlay: Overlay,
+
+    /// What the cursor snaps to while drawing
+    pub snapping: Snapping,
+
+    /// Grid the selected region is replicated into
+    pub grid: Grid,
+
+    /// Distance around the selected rectangles the ink is looked for when
+    /// fitting them to their content, in pixels of the original image
+    pub fit_margin: f32,
+
+    /// Cleanup of the page before its ink is keyed
+    pub preprocess: Preprocess,
+
+    /// Cleanup being edited, it becomes `preprocess` once a value is set,
+    /// not on every step of a drag, as the page takes a while to clean up
+    pub preprocess_draft: Preprocess,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            tool: Tool::Rectangle,
+            overlay: Overlay::default(),
+            snapping: Snapping::default(),
+            grid: Grid::default(),
+            fit_margin: 10.0,
+            preprocess: Preprocess::default(),
+            preprocess_draft: Preprocess::default()
+        }
+    }
+}
+
+/// Grid of copies of a region, like the subplots of a figure
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Grid {
+    pub rows: u32,
+    pub columns: u32,
+
+    /// Space between the cells, in pixels of the original image
+    pub spacing: (f32, f32),
+}
+
+impl Default for Grid {
+    fn default() -> Self {
+        Self {
+            rows: 2,
+            columns: 3,
+            spacing: (0.0, 0.0)
+        }
+    }
+}
+
+/// What the color sampled by the eyedropper is used as
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EyedropperTarget {
+    /// Color of the ink on the crop
+    Color,
+
+    /// Color of the ink to detect
+    KeyColor,
+}
+
+/// State of the color tools of the panel
+pub(crate) struct ColorTools {
+    /// Palettes loaded from the config file
+    palettes: Vec<Palette>,
+
+    /// Index of the palette shown
+    palette: usize,
+
+    /// Colors recently given to a region, the newest first
+    recent: Vec<Color32>,
+
+    /// Color picked but not yet remembered as recent, it's remembered once
+    /// the pointer is released so dragging on the picker doesn't flood them
+    pending: Option<Color32>,
+
+    /// Text of the hex input while it's being edited
+    hex: String,
+
+    /// Side of the square of pixels averaged by the eyedropper
+    pub patch_size: u32,
+
+    pub target: EyedropperTarget,
+}
+
+impl ColorTools {
+    pub fn new(palettes: Vec<Palette>) -> Self {
+        Self {
+            palettes,
+            palette: 0,
+            recent: Vec::new(),
+            pending: None,
+            hex: String::new(),
+            patch_size: 1,
+            target: EyedropperTarget::Color
+        }
+    }
+
+    /// Remember `color` as the most recently used one
+    pub fn push_recent(&mut self, color: Color32) {
+        self.recent.retain(|recent| *recent != color);
+        self.recent.insert(0, color);
+        self.recent.truncate(MAX_RECENT_COLORS);
+    }
+
+    /// Apply a color sampled from the image on the selected regions, it's
+    /// only remembered if there are none
+    pub fn apply_sample(&mut self, regions: &mut Regions, color: Color32) {
+        let target = self.target;
+        regions.edit_selected(|region| match target {
+            EyedropperTarget::Color => region.color = color,
+            EyedropperTarget::KeyColor => region.key_color = Some(color),
+        });
+        self.push_recent(color);
+    }
+}
+
+/// Change on the list of regions requested from one of its rows, applied
+/// after the list is drawn as it can't be modified while iterating it
+enum ListAction {
+    Select(usize),
+
+    /// Add or remove from the selection, Shift+click
+    ToggleSelection(usize),
+    MoveUp(usize),
+    MoveDown(usize),
+    Delete(usize),
+
+    /// Show or hide the region on the image
+    Show(usize, bool),
+}
+
+/// Side panel with the drawing tools, the list of regions and the properties
+/// of the selected one, the changes are applied directly on `regions`
+///
+/// `image` is the original image, the coordinates are clamped to its size
+pub(crate) fn regions_panel(
+    ctx: &egui::Context,
+    regions: &mut Regions,
+    settings: &mut Settings,
+    colors: &mut ColorTools,
+    image: &Image
+) {
+    let size = image.dimensions();
+    let Settings {
+        tool,
+        overlay,
+        snapping,
+        grid,
+        fit_margin,
+        preprocess,
+        preprocess_draft
+    } = settings;
+    egui::SidePanel::left("regions").show(ctx, |ui| {
+        ui.horizontal_wrapped(|ui| {
+            ui.selectable_value(tool, Tool::Rectangle, "▭ Rectangle");
+            ui.selectable_value(tool, Tool::Polygon, "⬠ Polygon");
+            ui.selectable_value(tool, Tool::Select, "↖ Select");
+            ui.selectable_value(tool, Tool::Eyedropper, "💧 Eyedropper");
+        });
+        if *tool == Tool::Eyedropper {
+            ui.horizontal(|ui| {
+                ui.label("Sample");
+                ui.add(egui::DragValue::new(&mut colors.patch_size)
+                    .clamp_range(1..=15)
+                    .suffix(" px"));
+                ui.radio_value(&mut colors.target,
+                    EyedropperTarget::Color, "as color");
+                ui.radio_value(&mut colors.target,
+                    EyedropperTarget::KeyColor, "as ink");
+            });
+        }
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut overlay.visible, "Overlays")
+                .on_hover_text("Toggle with H");
+            ui.checkbox(&mut overlay.labels, "Labels");
+            ui.checkbox(&mut overlay.loupe, "Loupe")
+                .on_hover_text("Toggle with L");
+            ui.add(egui::Slider::new(&mut overlay.fill_alpha, 0..=255)
+                .text("Fill"));
+        });
+        ui.horizontal_wrapped(|ui| {
+            ui.checkbox(&mut snapping.enabled, "Snap")
+                .on_hover_text("Hold Ctrl to draw without snapping");
+            ui.add_enabled_ui(snapping.enabled, |ui| {
+                ui.checkbox(&mut snapping.regions, "regions");
+                ui.checkbox(&mut snapping.border, "border");
+                ui.checkbox(&mut snapping.lines, "lines");
+            });
+        });
+        if preprocess_editor(ui, preprocess_draft) {
+            *preprocess = *preprocess_draft;
+        }
+        ui.separator();
+
+        constraint_editor(ui, regions);
+        scheme_editor(ui, regions, &colors.palettes);
+        ui.separator();
+
+        ui.heading("Regions");
+        let action = region_list(ui, regions);
+        match action {
+            Some(ListAction::Select(idx)) => regions.select(idx),
+            Some(ListAction::ToggleSelection(idx)) => {
+                regions.toggle_selection(idx);
+            }
+            Some(ListAction::MoveUp(idx)) => regions.swap(idx - 1, idx),
+            Some(ListAction::MoveDown(idx)) => regions.swap(idx, idx + 1),
+            Some(ListAction::Delete(idx)) => {
+                regions.remove(idx);
+            }
+            Some(ListAction::Show(idx, visible)) => {
+                regions.edit(idx, |region| region.visible = visible);
+            }
+            None => {}
+        }
+        ui.separator();
+
+        if regions.selection().len() > 1 {
+            group_editor(ui, regions);
+            ui.separator();
+        }
+
+        let constraint = regions.constraint();
+        match regions.selected_index() {
+            Some(idx) => {
+                ui.heading("Properties");
+                let (fit, ink, edited) = regions.edit(idx, |region| {
+                    ui.horizontal(|ui| {
+                        ui.label("Name");
+                        ui.text_edit_singleline(&mut region.name);
+                    });
+                    let before = region.state.clone();
+                    geometry_editor(ui, &mut region.state, size);
+                    if region.state != before {
+                        region.constrain(constraint, size);
+                    }
+                    let fit = ui.horizontal(|ui| {
+                        let fit = ui.button("Fit to content")
+                            .on_hover_text("Shrink or grow the selected \
+                                rectangles to the ink around them")
+                            .clicked();
+                        ui.label("within");
+                        ui.add(egui::DragValue::new(&mut *fit_margin)
+                            .clamp_range(0.0..=200.0)
+                            .suffix(" px"));
+                        fit
+                    }).inner;
+
+                    let ink = (region.color, region.key_color, region.threshold);
+                    color_editor(ui, region, colors);
+                    let edited = (region.color, region.key_color, region.threshold);
+                    (fit, ink, edited)
+                }).unwrap();
+
+                // The ink setting changed is copied to the whole selection,
+                // the others are kept
+                let (color, key_color, threshold) = edited;
+                if color != ink.0 {
+                    regions.edit_selected(|region| region.color = color);
+                }
+                if key_color != ink.1 {
+                    regions.edit_selected(|region| region.key_color = key_color);
+                }
+                if threshold != ink.2 {
+                    regions.edit_selected(|region| region.threshold = threshold);
+                }
+                ui.separator();
+
+                let (duplicate, replicate) = copies_editor(ui, grid);
+                if fit {
+                    regions.fit_selected_to_content(image, *fit_margin);
+                }
+                if duplicate {
+                    let selected = regions.selected_regions();
+                    regions.paste(&selected, DUPLICATE_OFFSET, DUPLICATE_OFFSET);
+                }
+                if replicate {
+                    regions.replicate(idx, grid.rows, grid.columns, grid.spacing);
+                }
+            }
+            None => {
+                ui.label("Select a region to edit it");
+            }
+        }
+    });
+}
+
+/// Cleanup of scanned pages, the page shown and the previews follow it
+///
+/// Returns if an edit is done, the values being dragged or typed aren't till
+/// they are released or lose the focus.
+fn preprocess_editor(ui: &mut egui::Ui, preprocess: &mut Preprocess) -> bool {
+    let set = |response: egui::Response| {
+        response.drag_released() || response.lost_focus()
+    };
+
+    ui.collapsing("Clean up the page", |ui| {
+        let mut done = ui.checkbox(&mut preprocess.normalize_paper, "White paper")
+            .on_hover_text("Scale the colors so the paper becomes white")
+            .changed();
+        ui.horizontal(|ui| {
+            ui.label("Background");
+            let binarize = &mut preprocess.binarize;
+            done |= ui.radio_value(binarize, Binarize::None, "keep").changed();
+            done |= ui.radio_value(binarize, Binarize::Otsu, "Otsu")
+                .on_hover_text("One threshold for the whole page")
+                .changed();
+            let sauvola = matches!(binarize, Binarize::Sauvola { .. });
+            if ui.radio(sauvola, "Sauvola")
+                    .on_hover_text("A threshold around every pixel, for \
+                        stains and uneven lighting")
+                    .clicked() && !sauvola {
+                *binarize = Binarize::sauvola();
+                done = true;
+            }
+        });
+        if let Binarize::Sauvola { window, k } = &mut preprocess.binarize {
+            ui.horizontal(|ui| {
+                ui.label("Window");
+                done |= set(ui.add(egui::DragValue::new(window)
+                    .clamp_range(3..=255)
+                    .suffix(" px")));
+                ui.label("k");
+                done |= set(ui.add(egui::DragValue::new(k)
+                    .speed(0.01)
+                    .clamp_range(0.0..=1.0)));
+            });
+        }
+        ui.horizontal(|ui| {
+            ui.label("Remove specks under");
+            done |= set(ui.add(egui::DragValue::new(&mut preprocess.despeckle)
+                .clamp_range(0..=500)
+                .suffix(" px")));
+        });
+
+        done
+    }).body_returned.unwrap_or(false)
+}
+
+/// Operations on all the selected regions at once
+fn group_editor(ui: &mut egui::Ui, regions: &mut Regions) {
+    ui.heading(format!("{} regions selected", regions.selection().len()));
+    ui.horizontal_wrapped(|ui| {
+        ui.label("Align");
+        let aligns = [
+            (Align::Left, "⏴ left"),
+            (Align::CenterX, "center"),
+            (Align::Right, "right ⏵"),
+            (Align::Top, "⏶ top"),
+            (Align::CenterY, "middle"),
+            (Align::Bottom, "bottom ⏷"),
+        ];
+        for (align, label) in aligns {
+            if ui.small_button(label).clicked() {
+                regions.align_selected(align);
+            }
+        }
+    });
+    ui.horizontal(|ui| {
+        ui.label("Distribute");
+        if ui.small_button("horizontally").clicked() {
+            regions.distribute_selected(true);
+        }
+        if ui.small_button("vertically").clicked() {
+            regions.distribute_selected(false);
+        }
+    });
+    if ui.button("🗑 Delete selected").clicked() {
+        regions.remove_selected();
+    }
+}
+
+/// Buttons to duplicate the selected region or to replicate it into `grid`
+///
+/// Returns if the region has to be duplicated and if it has to be replicated
+fn copies_editor(ui: &mut egui::Ui, grid: &mut Grid) -> (bool, bool) {
+    let duplicate = ui.button("Duplicate")
+        .on_hover_text("Ctrl+D, copy with Ctrl+C and paste at the cursor \
+            with Ctrl+V")
+        .clicked();
+
+    let mut replicate = false;
+    ui.collapsing("Replicate into grid", |ui| {
+        egui::Grid::new("replicate").show(ui, |ui| {
+            ui.label("Rows");
+            ui.add(egui::DragValue::new(&mut grid.rows).clamp_range(1..=50));
+            ui.label("Columns");
+            ui.add(egui::DragValue::new(&mut grid.columns).clamp_range(1..=50));
+            ui.end_row();
+            ui.label("Spacing x");
+            ui.add(egui::DragValue::new(&mut grid.spacing.0).suffix(" px"));
+            ui.label("y");
+            ui.add(egui::DragValue::new(&mut grid.spacing.1).suffix(" px"));
+            ui.end_row();
+        });
+        replicate = ui.button("Replicate")
+            .on_hover_text("The selected region is the top left cell")
+            .clicked();
+    });
+
+    (duplicate, replicate)
+}
+
+/// Picker of the shape the new rectangles are restricted to
+fn constraint_editor(ui: &mut egui::Ui, regions: &mut Regions) {
+    let mut constraint = regions.constraint();
+    ui.horizontal(|ui| {
+        ui.label("Shape");
+        if ui.radio(constraint == Constraint::Free, "free").clicked() {
+            constraint = Constraint::Free;
+        }
+        let aspect = matches!(constraint, Constraint::Aspect(_));
+        if ui.radio(aspect, "aspect").clicked() && !aspect {
+            constraint = Constraint::Aspect(4.0 / 3.0);
+        }
+        let size = matches!(constraint, Constraint::Size(..));
+        if ui.radio(size, "size").clicked() && !size {
+            constraint = Constraint::Size(400.0, 300.0);
+        }
+    });
+
+    ui.horizontal(|ui| {
+        match &mut constraint {
+            Constraint::Free => {}
+            Constraint::Aspect(aspect) => {
+                ui.label("Width / height");
+                ui.add(egui::DragValue::new(aspect)
+                    .speed(0.01)
+                    .clamp_range(0.01..=100.0));
+            }
+            Constraint::Size(width, height) => {
+                ui.add(egui::DragValue::new(width)
+                    .clamp_range(1.0..=f32::MAX)
+                    .suffix(" px"));
+                ui.label("×");
+                ui.add(egui::DragValue::new(height)
+                    .clamp_range(1.0..=f32::MAX)
+                    .suffix(" px"));
+            }
+        }
+
+        let selected = regions.selected().and_then(Region::size).is_some();
+        if ui.add_enabled(selected, egui::Button::new("Match selected"))
+                .on_hover_text("Draw the new rectangles the size of the \
+                    selected region")
+                .clicked() {
+            regions.match_selected_size();
+            constraint = regions.constraint();
+        }
+    });
+    regions.set_constraint(constraint);
+}
+
+/// Picker of the color scheme given to the new regions, which can also
+/// recolor all the existing ones
+fn scheme_editor(ui: &mut egui::Ui, regions: &mut Regions, palettes: &[Palette]) {
+    let schemes: Vec<ColorScheme> = ColorScheme::builtin()
+        .into_iter()
+        .chain(palettes.iter().cloned().map(ColorScheme::Custom))
+        .collect();
+
+    // The first entry is no scheme
+    let mut selected = regions.scheme()
+        .and_then(|scheme| schemes.iter().position(|s| s == scheme))
+        .map_or(0, |idx| idx + 1);
+    let response = egui::ComboBox::from_label("Color scheme").show_index(
+        ui,
+        &mut selected,
+        schemes.len() + 1,
+        |idx| match idx {
+            0 => "none".to_owned(),
+            idx => schemes[idx - 1].name().to_owned()
+        }
+    );
+    if response.changed() {
+        regions.set_scheme(selected.checked_sub(1)
+            .map(|idx| schemes[idx].clone()));
+    }
+
+    if let Some(scheme) = regions.scheme().cloned() {
+        ui.horizontal(|ui| {
+            for color in scheme.colors() {
+                show_color(ui, *color, egui::vec2(SWATCH_SIZE, SWATCH_SIZE));
+            }
+        });
+        if ui.button("Recolor all regions").clicked() {
+            regions.recolor(&scheme);
+        }
+    }
+}
+
+/// List of the regions in crop order, one row per region
+///
+/// Returns the action requested from a row, if any
+fn region_list(ui: &mut egui::Ui, regions: &Regions) -> Option<ListAction> {
+    let mut action = None;
+    let selection = regions.selection().to_vec();
+    let shift = ui.input().modifiers.shift;
+    let len = regions.len();
+    egui::ScrollArea::vertical()
+        .max_height(ui.available_height() / 2.0)
+        .show(ui, |ui|
+    {
+        for (idx, region) in regions.iter().enumerate() {
+            ui.horizontal(|ui| {
+                let mut visible = region.visible;
+                if ui.checkbox(&mut visible, "")
+                        .on_hover_text("Show on the image")
+                        .changed() {
+                    action = Some(ListAction::Show(idx, visible));
+                }
+                show_color(ui, region.color, egui::vec2(12.0, 12.0));
+
+                let label = match region.size() {
+                    Some((width, height)) => format!("{} ({:.0}×{:.0})",
+                        region.name, width, height),
+                    None => format!("{} (drawing)", region.name)
+                };
+                if ui.selectable_label(selection.contains(&idx), label)
+                        .clicked() {
+                    action = Some(if shift {
+                        ListAction::ToggleSelection(idx)
+                    } else {
+                        ListAction::Select(idx)
+                    });
+                }
+
+                if ui.add_enabled(idx > 0, egui::Button::new("⬆").small())
+                        .clicked() {
+                    action = Some(ListAction::MoveUp(idx));
+                }
+                if ui.add_enabled(idx + 1 < len, egui::Button::new("⬇").small())
+                        .clicked() {
+                    action = Some(ListAction::MoveDown(idx));
+                }
+                if ui.small_button("🗑").clicked() {
+                    action = Some(ListAction::Delete(idx));
+                }
+            });
+        }
+    });
+
+    action
+}
+
+/// Numeric editor of the coordinates of a region, in pixels of the original
+/// image
+fn geometry_editor(
+    ui: &mut egui::Ui,
+    state: &mut RegionState,
+    (width, height): (u32, u32)
+) {
+    let (width, height) = (width as f32, height as f32);
+    let coordinate = |ui: &mut egui::Ui, label: &str, value: &mut f32, max| {
+        ui.label(label);
+        ui.add(egui::DragValue::new(value)
+            .speed(1.0)
+            .clamp_range(0.0..=max));
+    };
+
+    match state {
+        RegionState::Complete { x1, y1, x2, y2 } => {
+            egui::Grid::new("rect-coordinates").show(ui, |ui| {
+                coordinate(ui, "x1", x1, width);
+                coordinate(ui, "y1", y1, height);
+                ui.end_row();
+                coordinate(ui, "x2", x2, width);
+                coordinate(ui, "y2", y2, height);
+                ui.end_row();
+            });
+
+            // Keep (x1, y1) as the top left corner
+            if x1 > x2 {
+                std::mem::swap(x1, x2);
+            }
+            if y1 > y2 {
+                std::mem::swap(y1, y2);
+            }
+        }
+        RegionState::Polygon { points } => {
+            egui::ScrollArea::vertical()
+                .id_source("polygon-coordinates")
+                .max_height(120.0)
+                .show(ui, |ui|
+            {
+                egui::Grid::new("polygon-coordinates").show(ui, |ui| {
+                    for (x, y) in points.iter_mut() {
+                        coordinate(ui, "x", x, width);
+                        coordinate(ui, "y", y, height);
+                        ui.end_row();
+                    }
+                });
+            });
+        }
+        _ => {
+            ui.label("The region is still being drawn");
+        }
+    }
+}
+
+/// Editor of the color of the region and of the ink it extracts
+fn color_editor(ui: &mut egui::Ui, region: &mut Region, colors: &mut ColorTools) {
+    let before = region.color;
+    color_picker_color32(ui, &mut region.color, Alpha::Opaque);
+
+    // Hex and RGB inputs
+    ui.horizontal(|ui| {
+        let hex = ui.add(egui::TextEdit::singleline(&mut colors.hex)
+            .desired_width(64.0));
+        if hex.lost_focus() {
+            if let Some(color) = parse_hex(&colors.hex) {
+                region.color = color;
+            }
+        }
+        if !hex.has_focus() {
+            colors.hex = to_hex(region.color);
+        }
+
+        let [mut r, mut g, mut b, _] = region.color.to_array();
+        for (label, channel) in [("R", &mut r), ("G", &mut g), ("B", &mut b)] {
+            ui.label(label);
+            ui.add(egui::DragValue::new(channel));
+        }
+        region.color = Color32::from_rgb(r, g, b);
+    });
+
+    // Recent colors and palettes
+    if let Some(color) = swatches(ui, "Recent", &colors.recent) {
+        region.color = color;
+    }
+    if !colors.palettes.is_empty() {
+        let palettes = &colors.palettes;
+        egui::ComboBox::from_label("Palette").show_index(
+            ui,
+            &mut colors.palette,
+            palettes.len(),
+            |idx| palettes[idx].name.clone()
+        );
+        let palette = &colors.palettes[colors.palette];
+        if let Some(color) = swatches(ui, "", &palette.colors) {
+            region.color = color;
+        }
+    }
+
+    if region.color != before {
+        colors.pending = Some(region.color);
+    }
+    if !ui.input().pointer.any_down() {
+        if let Some(color) = colors.pending.take() {
+            colors.push_recent(color);
+        }
+    }
+    ui.separator();
+
+    // Ink detection
+    ui.horizontal(|ui| {
+        let mut keyed = region.key_color.is_some();
+        ui.checkbox(&mut keyed, "Ink color")
+            .on_hover_text("Extract the ink of this color instead of black");
+        match (keyed, &mut region.key_color) {
+            (true, Some(key)) => {
+                egui::widgets::color_picker::color_edit_button_srgba(
+                    ui, key, Alpha::Opaque);
+            }
+            (true, key @ None) => *key = Some(Color32::BLACK),
+            (false, key) => *key = None
+        }
+    });
+    ui.add(egui::Slider::new(&mut region.threshold, 0..=255)
+        .text("Ink threshold"));
+}
+
+/// Row of clickable color swatches
+///
+/// Returns the color clicked, if any
+fn swatches(ui: &mut egui::Ui, label: &str, colors: &[Color32]) -> Option<Color32> {
+    let mut clicked = None;
+    ui.horizontal_wrapped(|ui| {
+        if !label.is_empty() {
+            ui.label(label);
+        }
+        for color in colors {
+            let swatch = show_color(ui, *color, egui::vec2(SWATCH_SIZE, SWATCH_SIZE))
+                .interact(egui::Sense::click())
+                .on_hover_text(to_hex(*color));
+            if swatch.clicked() {
+                clicked = Some(*color);
+            }
+        }
+    });
+
+    clicked
+}
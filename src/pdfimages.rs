@@ -0,0 +1,298 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::error::{Error, Result};
+
+/// Metadata of an image embedded in a pdf, as reported by `pdfimages -list`
+#[derive(Debug, Clone)]
+pub struct ImageInfo {
+    /// Page where the image is drawn
+    pub page: u32,
+
+    /// Index of the image on the whole pdf, the extracted files are numbered
+    /// from the first page extracted instead, see [`find_image`]
+    pub num: u32,
+
+    /// Kind of image, `image`, `mask`, `smask` or `stencil`
+    pub kind: String,
+
+    pub width: u32,
+    pub height: u32,
+
+    /// Density of the image as drawn on the page, in pixels per inch
+    pub x_ppi: f32,
+    pub y_ppi: f32,
+}
+
+/// Smallest side, in pixels, of the images taken for plots by default
+pub const MIN_IMAGE_SIZE: u32 = 64;
+
+impl ImageInfo {
+    /// If it's a picture with both sides of at least `min_size` pixels,
+    /// rather than a mask or a small decoration like a logo
+    pub fn is_picture(&self, min_size: u32) -> bool {
+        self.kind == "image" && self.width >= min_size && self.height >= min_size
+    }
+}
+
+/// Inclusive range of pages of a pdf, counted from 1
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageRange {
+    pub first: u32,
+    pub last: u32,
+}
+
+impl PageRange {
+    pub fn new(first: u32, last: u32) -> Self {
+        Self {
+            first,
+            last
+        }
+    }
+
+    pub fn contains(&self, page: u32) -> bool {
+        (self.first..=self.last).contains(&page)
+    }
+
+    /// The pages of `ranges` as sorted ranges that don't overlap or touch,
+    /// so no page is extracted twice
+    pub fn merge(ranges: &[PageRange]) -> Vec<PageRange> {
+        let mut sorted = ranges.to_vec();
+        sorted.sort_by_key(|range| range.first);
+
+        let mut merged: Vec<PageRange> = Vec::new();
+        for range in sorted {
+            match merged.last_mut() {
+                Some(last) if range.first <= last.last.saturating_add(1) => {
+                    last.last = last.last.max(range.last);
+                }
+                _ => merged.push(range),
+            }
+        }
+
+        merged
+    }
+}
+
+impl fmt::Display for PageRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.first == self.last {
+            write!(f, "{}", self.first)
+        } else {
+            write!(f, "{}-{}", self.first, self.last)
+        }
+    }
+}
+
+/// Extract the images of `pdf` drawn on `pages`, or on every page if it's
+/// empty, to files named `root` followed by their page and image numbers,
+/// like `img-003-012.ppm`
+pub fn extract(pdf: &Path, root: &Path, pages: &[PageRange]) -> Result<()> {
+    let mut calls: Vec<Vec<String>> = PageRange::merge(pages).iter()
+        .map(|range| vec![
+            "-f".to_owned(), range.first.to_string(),
+            "-l".to_owned(), range.last.to_string()
+        ])
+        .collect();
+    if calls.is_empty() {
+        calls.push(Vec::new());
+    }
+
+    for range in calls {
+        let output = Command::new("pdfimages")
+            .arg("-p")
+            .args(range)
+            .arg(pdf)
+            .arg(root)
+            .output()?;
+        if !output.status.success() {
+            return Err(Error::Pdfimages(
+                String::from_utf8_lossy(&output.stderr).trim().to_owned()));
+        }
+    }
+
+    Ok(())
+}
+
+/// List the images embedded in `pdf`
+pub fn list(pdf: &Path) -> Result<Vec<ImageInfo>> {
+    let output = Command::new("pdfimages")
+        .arg("-list")
+        .arg(pdf)
+        .output()?;
+    if !output.status.success() {
+        return Err(Error::Pdfimages(
+            String::from_utf8_lossy(&output.stderr).into_owned()));
+    }
+
+    parse_list(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Parse the table printed by `pdfimages -list`, the columns are located by
+/// their header so small differences between versions don't matter
+fn parse_list(table: &str) -> Result<Vec<ImageInfo>> {
+    let mut lines = table.lines();
+    let header: Vec<&str> = lines.next()
+        .ok_or_else(|| Error::Pdfimages("empty `-list` output".to_owned()))?
+        .split_whitespace()
+        .collect();
+    let column = |name: &str| header.iter()
+        .position(|column| *column == name)
+        .ok_or_else(|| Error::Pdfimages(
+            format!("missing column `{}` on `-list` output", name)));
+    let page = column("page")?;
+    let num = column("num")?;
+    let kind = column("type")?;
+    let width = column("width")?;
+    let height = column("height")?;
+    let x_ppi = column("x-ppi")?;
+    let y_ppi = column("y-ppi")?;
+
+    let mut infos = Vec::new();
+    for line in lines {
+        // Skip the separator line
+        if line.starts_with('-') || line.trim().is_empty() {
+            continue;
+        }
+
+        let malformed = || Error::Pdfimages(
+            format!("malformed `-list` line: {}", line));
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let field = |idx: usize| fields.get(idx).copied()
+            .ok_or_else(malformed);
+        let number = |idx: usize| field(idx)?
+            .parse::<u32>()
+            .map_err(|_| malformed());
+        let ppi = |idx: usize| field(idx)?
+            .parse::<f32>()
+            .map_err(|_| malformed());
+        infos.push(ImageInfo {
+            page: number(page)?,
+            num: number(num)?,
+            kind: field(kind)?.to_owned(),
+            width: number(width)?,
+            height: number(height)?,
+            x_ppi: ppi(x_ppi)?,
+            y_ppi: ppi(y_ppi)?,
+        });
+    }
+
+    Ok(infos)
+}
+
+/// Extract the image index from the name of a file produced by `pdfimages`,
+/// for example `img-003.ppm` and `img-001-003.ppm` are the image 3 counting
+/// from the first page extracted
+pub fn image_num(path: &Path) -> Option<u32> {
+    path.file_stem()?
+        .to_str()?
+        .rsplit('-')
+        .next()?
+        .parse()
+        .ok()
+}
+
+/// Extract the page from the name of a file produced by `pdfimages -p`, for
+/// example `img-001-003.ppm` is on the page 1
+pub fn image_page(path: &Path) -> Option<u32> {
+    path.file_stem()?
+        .to_str()?
+        .rsplit('-')
+        .nth(1)?
+        .parse()
+        .ok()
+}
+
+/// File of the image `num` of `infos` among the `paths` extracted with
+/// `pdfimages -p`, found by its page and its position on the page as the
+/// files are numbered from the first page extracted
+pub fn find_image<'a>(
+    paths: &'a [PathBuf],
+    infos: &[ImageInfo],
+    num: u32
+) -> Option<&'a PathBuf> {
+    let info = infos.iter().find(|info| info.num == num)?;
+    let position = infos.iter()
+        .filter(|other| other.page == info.page && other.num < num)
+        .count();
+
+    let mut on_page: Vec<&PathBuf> = paths.iter()
+        .filter(|path| image_page(path) == Some(info.page))
+        .collect();
+    on_page.sort_by_key(|path| image_num(path));
+
+    on_page.get(position).copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIST: &str = "\
+page   num  type   width height color comp bpc  enc interp  object ID x-ppi y-ppi size ratio
+--------------------------------------------------------------------------------------------
+   1     0 image     640   480  rgb     3   8  jpeg   no         9  0   150   150 45.2K 5.0%
+   1     1 smask     640   480  gray    1   8  image  no         9  0   150   150 1234B 0.4%
+   3     2 image      32    32  rgb     3   8  image  no        21  0    72  72.5  1.1K 36%
+";
+
+    #[test]
+    fn parse_list_reads_the_columns() {
+        let infos = parse_list(LIST).unwrap();
+
+        assert_eq!(infos.len(), 3);
+        assert_eq!((infos[0].page, infos[0].num), (1, 0));
+        assert_eq!(infos[1].kind, "smask");
+        assert_eq!((infos[2].page, infos[2].num), (3, 2));
+        assert_eq!((infos[2].width, infos[2].height), (32, 32));
+        assert_eq!((infos[2].x_ppi, infos[2].y_ppi), (72.0, 72.5));
+        assert!(infos[0].is_picture(MIN_IMAGE_SIZE));
+        assert!(!infos[1].is_picture(MIN_IMAGE_SIZE));
+        assert!(!infos[2].is_picture(MIN_IMAGE_SIZE));
+    }
+
+    #[test]
+    fn parse_list_without_images() {
+        let header = LIST.lines().take(2).collect::<Vec<_>>().join("\n");
+        assert!(parse_list(&header).unwrap().is_empty());
+    }
+
+    #[test]
+    fn parse_list_rejects_malformed_tables() {
+        assert!(parse_list("").is_err());
+        assert!(parse_list("page num type width height\n1 0 image 10 10").is_err());
+        let truncated = LIST.replace("   150   150 45.2K 5.0%", "");
+        assert!(parse_list(&truncated).is_err());
+    }
+
+    #[test]
+    fn image_page_and_num_come_from_the_file_name() {
+        let path = Path::new("out/img-003-012.ppm");
+        assert_eq!(image_page(path), Some(3));
+        assert_eq!(image_num(path), Some(12));
+        assert_eq!(image_page(Path::new("img-012.ppm")), None);
+        assert_eq!(image_num(Path::new("img-012.ppm")), Some(12));
+    }
+
+    #[test]
+    fn find_image_matches_by_page_and_position() {
+        let infos = parse_list(LIST).unwrap();
+
+        // Extracted from the page 3 on, so its image is numbered 0
+        let paths = [PathBuf::from("img-003-000.ppm")];
+        assert_eq!(find_image(&paths, &infos, 2), Some(&paths[0]));
+        assert_eq!(find_image(&paths, &infos, 0), None);
+
+        // Every page, the numbers match the ones of the list
+        let paths = [
+            PathBuf::from("img-003-002.ppm"),
+            PathBuf::from("img-001-001.ppm"),
+            PathBuf::from("img-001-000.ppm"),
+        ];
+        assert_eq!(find_image(&paths, &infos, 0), Some(&paths[2]));
+        assert_eq!(find_image(&paths, &infos, 1), Some(&paths[1]));
+        assert_eq!(find_image(&paths, &infos, 2), Some(&paths[0]));
+        assert_eq!(find_image(&paths, &infos, 3), None);
+    }
+}
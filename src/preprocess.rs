@@ -0,0 +1,374 @@
+use image::{Rgba, RgbaImage};
+
+use crate::image::Image;
+
+/// Dynamic range of the standard deviation on Sauvola's threshold
+const SAUVOLA_RANGE: f32 = 128.0;
+
+/// Window and `k` of Sauvola's threshold that suit most scans
+const SAUVOLA_WINDOW: u32 = 31;
+const SAUVOLA_K: f32 = 0.2;
+
+/// Deviation below which a window is too flat for Sauvola's threshold to
+/// tell a solid fill from paper, so the global threshold decides
+const FLAT_DEVIATION: f32 = SAUVOLA_RANGE / 2.0;
+
+/// Lightness around the most common light one still counted as paper
+const PAPER_SPREAD: i32 = 8;
+
+const WHITE: Rgba<u8> = Rgba([255, 255, 255, 255]);
+
+/// How the ink is told apart from the paper
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Binarize {
+    /// Keep the paper as it is, only the specks are removed
+    None,
+
+    /// One threshold for the whole page, the one that best splits the
+    /// lightness of the ink from the paper
+    Otsu,
+
+    /// A threshold for every pixel from the mean and deviation of the
+    /// lightness on the `window` around it, which follows stains and uneven
+    /// lighting, the higher `k` the darker the ink has to be
+    Sauvola {
+        window: u32,
+        k: f32
+    },
+}
+
+/// Cleanup of scanned pages before their ink is keyed, yellowish paper and
+/// compression noise would otherwise end up on the crops as specks
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Preprocess {
+    /// Estimate the color of the paper and scale the channels so it's white
+    pub normalize_paper: bool,
+
+    /// Turn everything that isn't ink white, the ink keeps its color
+    pub binarize: Binarize,
+
+    /// Remove the specks of ink smaller than this many pixels, 0 keeps them
+    pub despeckle: u32,
+}
+
+impl Binarize {
+    /// Sauvola's threshold with the window and `k` that suit most scans
+    pub fn sauvola() -> Self {
+        Self::Sauvola {
+            window: SAUVOLA_WINDOW,
+            k: SAUVOLA_K
+        }
+    }
+}
+
+impl Default for Preprocess {
+    fn default() -> Self {
+        Self {
+            normalize_paper: false,
+            binarize: Binarize::None,
+            despeckle: 0
+        }
+    }
+}
+
+impl Preprocess {
+    pub fn is_enabled(&self) -> bool {
+        *self != Self::default()
+    }
+
+    /// Cleaned up copy of `image`
+    pub fn apply(&self, image: &Image) -> Image {
+        let mut image = image.clone();
+        let buffer = image.buffer_mut();
+        if self.normalize_paper {
+            let paper = paper_color(buffer);
+            for pixel in buffer.pixels_mut() {
+                for (channel, paper) in pixel.0.iter_mut().zip(paper.0).take(3) {
+                    let scaled = *channel as u32 * 255 / (paper as u32).max(1);
+                    *channel = scaled.min(255) as u8;
+                }
+            }
+        }
+
+        if self.binarize == Binarize::None && self.despeckle == 0 {
+            return image;
+        }
+        let (width, height) = buffer.dimensions();
+        let lightness: Vec<u8> = buffer.pixels().map(|pixel| luma(*pixel)).collect();
+        // Without binarizing the specks are still told apart from the paper
+        let threshold = otsu(&lightness);
+        let ink = match self.binarize {
+            Binarize::Otsu => {
+                lightness.iter().map(|value| *value <= threshold).collect()
+            }
+            Binarize::Sauvola { window, k } => {
+                sauvola(&lightness, (width, height), window, k, threshold)
+            }
+            Binarize::None => sauvola(
+                &lightness,
+                (width, height),
+                SAUVOLA_WINDOW,
+                SAUVOLA_K,
+                threshold
+            ),
+        };
+        let kept = if self.despeckle > 0 {
+            despeckle(&ink, (width, height), self.despeckle)
+        } else {
+            ink.clone()
+        };
+
+        let binarize = self.binarize != Binarize::None;
+        for ((pixel, ink), kept) in buffer.pixels_mut().zip(ink).zip(kept) {
+            if !kept && (ink || binarize) {
+                *pixel = WHITE;
+            }
+        }
+
+        image
+    }
+}
+
+/// Perceived lightness of `pixel`, from 0 to 255
+fn luma(pixel: Rgba<u8>) -> u8 {
+    let [r, g, b, _] = pixel.0;
+
+    ((299 * r as u32 + 587 * g as u32 + 114 * b as u32) / 1000) as u8
+}
+
+/// Mean color of the pixels with the most common lightness of the light
+/// half, white if the image is dark
+fn paper_color(buffer: &RgbaImage) -> Rgba<u8> {
+    let mut histogram = [0u32; 256];
+    for pixel in buffer.pixels() {
+        histogram[luma(*pixel) as usize] += 1;
+    }
+    let peak = match (128..256).max_by_key(|value| histogram[*value]) {
+        Some(peak) if histogram[peak] > 0 => peak as i32,
+        _ => return WHITE,
+    };
+
+    let mut sum = [0u64; 3];
+    let mut count = 0;
+    for pixel in buffer.pixels() {
+        if (luma(*pixel) as i32 - peak).abs() <= PAPER_SPREAD {
+            for (sum, channel) in sum.iter_mut().zip(pixel.0) {
+                *sum += channel as u64;
+            }
+            count += 1;
+        }
+    }
+    let [r, g, b] = sum.map(|sum| (sum / count) as u8);
+
+    Rgba([r, g, b, 255])
+}
+
+/// Threshold that maximizes the variance between the lightness of the pixels
+/// below it and above it
+fn otsu(lightness: &[u8]) -> u8 {
+    let mut histogram = [0u64; 256];
+    for value in lightness {
+        histogram[*value as usize] += 1;
+    }
+    let total = lightness.len() as f64;
+    let sum: f64 = histogram.iter()
+        .enumerate()
+        .map(|(value, count)| value as f64 * *count as f64)
+        .sum();
+
+    let (mut best, mut best_variance) = (0, 0.0);
+    let (mut dark, mut dark_sum) = (0.0, 0.0);
+    for (value, count) in histogram.iter().enumerate() {
+        dark += *count as f64;
+        dark_sum += value as f64 * *count as f64;
+        let light = total - dark;
+        if dark == 0.0 || light == 0.0 {
+            continue;
+        }
+        let difference = dark_sum / dark - (sum - dark_sum) / light;
+        let variance = dark * light * difference * difference;
+        if variance > best_variance {
+            (best, best_variance) = (value, variance);
+        }
+    }
+
+    best as u8
+}
+
+/// Pixels darker than Sauvola's threshold on the `window` around them,
+/// computed with summed area tables so the window size doesn't matter
+///
+/// On flat windows, like inside fills wider than the window, the pixels
+/// darker than the global `threshold` are ink too.
+fn sauvola(
+    lightness: &[u8],
+    (width, height): (u32, u32),
+    window: u32,
+    k: f32,
+    threshold: u8
+) -> Vec<bool> {
+    let (width, height) = (width as usize, height as usize);
+    let stride = width + 1;
+    let mut sums = vec![0u64; stride * (height + 1)];
+    let mut squares = vec![0u64; stride * (height + 1)];
+    for y in 0..height {
+        let (mut row_sum, mut row_squares) = (0, 0);
+        for x in 0..width {
+            let value = lightness[y * width + x] as u64;
+            row_sum += value;
+            row_squares += value * value;
+            let idx = (y + 1) * stride + x + 1;
+            sums[idx] = sums[idx - stride] + row_sum;
+            squares[idx] = squares[idx - stride] + row_squares;
+        }
+    }
+    let area = |table: &[u64], (x1, y1): (usize, usize), (x2, y2): (usize, usize)| {
+        table[y2 * stride + x2] + table[y1 * stride + x1]
+            - table[y1 * stride + x2] - table[y2 * stride + x1]
+    };
+
+    let half = (window / 2).max(1) as usize;
+    let mut ink = Vec::with_capacity(width * height);
+    for y in 0..height {
+        let (y1, y2) = (y.saturating_sub(half), (y + half + 1).min(height));
+        for x in 0..width {
+            let (x1, x2) = (x.saturating_sub(half), (x + half + 1).min(width));
+            let count = ((x2 - x1) * (y2 - y1)) as f32;
+            let mean = area(&sums, (x1, y1), (x2, y2)) as f32 / count;
+            let variance = area(&squares, (x1, y1), (x2, y2)) as f32 / count
+                - mean * mean;
+            let deviation = variance.max(0.0).sqrt();
+            let local = mean * (1.0 + k * (deviation / SAUVOLA_RANGE - 1.0));
+            let value = lightness[y * width + x];
+            ink.push(value as f32 <= local
+                || (value <= threshold && deviation < FLAT_DEVIATION));
+        }
+    }
+
+    ink
+}
+
+/// The `ink` without the groups of touching pixels smaller than `min_size`
+fn despeckle(ink: &[bool], (width, height): (u32, u32), min_size: u32) -> Vec<bool> {
+    let (width, height) = (width as usize, height as usize);
+    let mut kept = ink.to_vec();
+    let mut seen = vec![false; ink.len()];
+    let mut group = Vec::new();
+    let mut stack = Vec::new();
+    for start in 0..ink.len() {
+        if !ink[start] || seen[start] {
+            continue;
+        }
+
+        // Flood the group touching the pixel, diagonals included
+        seen[start] = true;
+        stack.push(start);
+        group.clear();
+        while let Some(idx) = stack.pop() {
+            group.push(idx);
+            let (x, y) = (idx % width, idx / width);
+            for ny in y.saturating_sub(1)..=(y + 1).min(height - 1) {
+                for nx in x.saturating_sub(1)..=(x + 1).min(width - 1) {
+                    let neighbour = ny * width + nx;
+                    if ink[neighbour] && !seen[neighbour] {
+                        seen[neighbour] = true;
+                        stack.push(neighbour);
+                    }
+                }
+            }
+        }
+
+        if group.len() < min_size as usize {
+            for idx in &group {
+                kept[*idx] = false;
+            }
+        }
+    }
+
+    kept
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn otsu_splits_two_levels() {
+        let lightness = [30, 32, 35, 31, 220, 225, 230, 228, 226, 224];
+        let threshold = otsu(&lightness);
+        assert!((35..220).contains(&threshold), "threshold {}", threshold);
+    }
+
+    #[test]
+    fn otsu_of_a_flat_image() {
+        assert_eq!(otsu(&[128; 16]), 0);
+        assert_eq!(otsu(&[]), 0);
+    }
+
+    #[test]
+    fn sauvola_follows_uneven_paper() {
+        // Paper getting darker to the right, the ink on the left is lighter
+        // than the paper on the right so no single threshold splits them
+        let (width, height) = (40, 9);
+        let mut lightness = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                let paper = 230 - 3 * x as u8;
+                let ink = y == 4 && (x == 10 || x == 30);
+                lightness.push(if ink { paper - 80 } else { paper });
+            }
+        }
+        assert!(lightness[4 * 40 + 10] > lightness[4 * 40 + 39]);
+
+        let ink = sauvola(&lightness, (width, height), 7, 0.2, 0);
+        let found: Vec<usize> = (0..ink.len()).filter(|idx| ink[*idx]).collect();
+        assert_eq!(found, [4 * 40 + 10, 4 * 40 + 30]);
+    }
+
+    #[test]
+    fn sauvola_fills_solid_squares() {
+        // A square wider than the window, its inside is as flat as paper
+        let (width, height) = (160, 160);
+        let inside = |x: u32, y: u32| {
+            (30..130).contains(&x) && (30..130).contains(&y)
+        };
+        let mut lightness = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                lightness.push(if inside(x, y) { 40 } else { 230 });
+            }
+        }
+        let center = (80 * width + 80) as usize;
+
+        let hollow = sauvola(
+            &lightness, (width, height), SAUVOLA_WINDOW, SAUVOLA_K, 0);
+        assert!(!hollow[center]);
+
+        let threshold = otsu(&lightness);
+        let ink = sauvola(
+            &lightness, (width, height), SAUVOLA_WINDOW, SAUVOLA_K, threshold);
+        for (idx, ink) in ink.iter().enumerate() {
+            let (x, y) = (idx as u32 % width, idx as u32 / width);
+            assert_eq!(*ink, inside(x, y), "pixel ({}, {})", x, y);
+        }
+    }
+
+    #[test]
+    fn despeckle_keeps_the_big_groups() {
+        // A speck, two pixels touching by a corner and a block of 4
+        let ink = [
+            true,  false, false, false, false, false,
+            false, false, false, false, true,  true,
+            false, false, true,  false, true,  true,
+            false, true,  false, false, false, false,
+        ];
+
+        let mut expected = ink;
+        expected[0] = false;
+        assert_eq!(despeckle(&ink, (6, 4), 2), expected);
+
+        let kept = despeckle(&ink, (6, 4), 3);
+        assert_eq!(kept.iter().filter(|pixel| **pixel).count(), 4);
+        assert_eq!(despeckle(&ink, (6, 4), 0), ink);
+    }
+}
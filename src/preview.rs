@@ -0,0 +1,135 @@
+use egui::{Color32, ColorImage, TextureFilter, TextureHandle};
+use image::RgbaImage;
+
+use crate::image::{is_ink, Image};
+use crate::regions::{Region, RegionState};
+
+/// Maximum size of the sides of the preview, in pixels
+const MAX_PREVIEW_SIZE: u32 = 320;
+
+/// Preview of the crop of a region, cached in steps so only the work that
+/// depends on what changed is redone: the geometry requires sampling the
+/// image again, the threshold or key color detecting the ink again and the
+/// color only recoloring it
+#[derive(Default)]
+pub struct Preview {
+    /// Geometry of the sampled region
+    state: Option<RegionState>,
+
+    /// Downscaled pixels of the original image inside the region bounds
+    source: RgbaImage,
+
+    /// Top left corner of `source` on the original image, and its scale
+    origin: (u32, u32),
+    scale: f32,
+
+    /// Key color and threshold used to detect the ink of `mask`
+    ink: Option<(Option<Color32>, u8)>,
+
+    /// Pixels of `source` detected as ink
+    mask: Vec<bool>,
+
+    /// Color of the ink on `texture`
+    color: Option<Color32>,
+
+    /// The processed crop uploaded to egui
+    texture: Option<TextureHandle>,
+}
+
+impl Preview {
+    /// Update the preview of the crop of `region` from `image`, with the ink
+    /// detected using its key color and threshold recolored with its color
+    ///
+    /// Returns the texture of the preview, `None` if the region is empty
+    pub fn update(
+        &mut self,
+        ctx: &egui::Context,
+        image: &Image,
+        region: &Region
+    ) -> Option<&TextureHandle> {
+        let (x1, y1, x2, y2) = region.bounds()?;
+        let (width, height) = image.dimensions();
+        let x1 = (x1.max(0.0) as u32).min(width);
+        let y1 = (y1.max(0.0) as u32).min(height);
+        let x2 = (x2.max(0.0) as u32).min(width);
+        let y2 = (y2.max(0.0) as u32).min(height);
+        if x2 <= x1 || y2 <= y1 {
+            return None;
+        }
+
+        if self.state.as_ref() != Some(&region.state) {
+            (self.source, self.scale) = sample(image.buffer(), (x1, y1, x2, y2));
+            self.origin = (x1, y1);
+            self.state = Some(region.state.clone());
+            self.ink = None;
+        }
+
+        let ink = (region.key_color, region.threshold);
+        if self.ink != Some(ink) {
+            // Polygons only keep the ink inside them, like on the export
+            let polygon = matches!(region.state, RegionState::Polygon { .. });
+            let (ox, oy) = self.origin;
+            let scale = self.scale;
+            self.mask = self.source.enumerate_pixels()
+                .map(|(x, y, pixel)| {
+                    let inside = !polygon || region.contains(
+                        ox as f32 + (x as f32 + 0.5) / scale,
+                        oy as f32 + (y as f32 + 0.5) / scale
+                    );
+                    inside && is_ink(*pixel, ink.0, ink.1)
+                })
+                .collect();
+            self.ink = Some(ink);
+            self.color = None;
+        }
+
+        let color = region.color;
+        if self.color != Some(color) || self.texture.is_none() {
+            let pixels = self.mask.iter()
+                .map(|ink| if *ink { color } else { Color32::TRANSPARENT })
+                .collect();
+            let preview = ColorImage {
+                size: [
+                    self.source.width() as usize,
+                    self.source.height() as usize
+                ],
+                pixels
+            };
+            match &mut self.texture {
+                Some(texture) => texture.set(preview, TextureFilter::Linear),
+                None => {
+                    self.texture = Some(ctx.load_texture(
+                        "crop-preview",
+                        preview,
+                        TextureFilter::Linear
+                    ));
+                }
+            }
+            self.color = Some(color);
+        }
+
+        self.texture.as_ref()
+    }
+}
+
+/// Sample the pixels inside `bounds` with nearest neighbour, downscaling them
+/// to fit on the preview, so the cost doesn't depend on the size of the scan
+///
+/// Returns the sampled pixels and the scale they were sampled at
+fn sample(
+    buffer: &RgbaImage,
+    (x1, y1, x2, y2): (u32, u32, u32, u32)
+) -> (RgbaImage, f32) {
+    let (width, height) = (x2 - x1, y2 - y1);
+    let scale = (MAX_PREVIEW_SIZE as f32 / width.max(height) as f32).min(1.0);
+    let preview_width = ((width as f32 * scale) as u32).max(1);
+    let preview_height = ((height as f32 * scale) as u32).max(1);
+
+    let sampled = RgbaImage::from_fn(preview_width, preview_height, |x, y| {
+        let sx = x1 + (x as f32 / scale) as u32;
+        let sy = y1 + (y as f32 / scale) as u32;
+        *buffer.get_pixel(sx.min(x2 - 1), sy.min(y2 - 1))
+    });
+
+    (sampled, scale)
+}
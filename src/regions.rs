@@ -1,8 +1,13 @@
 use std::mem;
 
+use image::Rgba;
 use tiny_skia::*;
 
+use crate::error::{Error, Result};
 use crate::image::Image;
+use crate::palette::ColorScheme;
+use crate::spatial::GridIndex;
+use crate::text;
 
 #[derive(Debug)]
 enum Line {
@@ -54,101 +59,474 @@ impl BoundLine {
                 start_y,
                 end_y
             } => {
-                if px <= x + self.margin && px >= x - self.margin {
-                    if py >= start_y - self.margin 
-                            && py <= end_y + self.margin {
-                        return true;
-                    }
-                }
-
-                return false;
+                px <= x + self.margin && px >= x - self.margin
+                    && py >= start_y - self.margin
+                    && py <= end_y + self.margin
             }
             Line::Horizontal {
                 y,
                 start_x,
                 end_x,
             } => {
-                if py <= y + self.margin && py >= y - self.margin {
-                    if px >= start_x - self.margin
-                            && px <= end_x + self.margin {
-                        return true;
-                    }
-                }
-
-                return false;
+                py <= y + self.margin && py >= y - self.margin
+                    && px >= start_x - self.margin
+                    && px <= end_x + self.margin
             }
         }
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+/// Geometry of a region, in pixels of the original image
+#[derive(Debug, Clone, PartialEq)]
 pub enum RegionState {
+    /// Only the first corner has been placed
     Start { 
         x1: f32,
         y1: f32 
     },
+
+    /// Rectangle with (x1, y1) as top left corner and (x2, y2) as bottom
+    /// right corner
     Complete { 
         x1: f32, 
         y1: f32, 
         x2: f32,
         y2: f32
+    },
+
+    /// Polygon whose points are still being placed
+    OpenPolygon {
+        points: Vec<(f32, f32)>
+    },
+
+    /// Closed polygon, only the ink inside it is cropped
+    Polygon {
+        points: Vec<(f32, f32)>
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+impl RegionState {
+    /// If the geometry is finished and can be cropped
+    pub fn is_complete(&self) -> bool {
+        matches!(self,
+            RegionState::Complete { .. } | RegionState::Polygon { .. })
+    }
+}
+
+/// Default of how far from black a pixel can be to be considered ink
+pub const DEFAULT_THRESHOLD: u8 = 200;
+
+/// A rectangle or polygon to crop from the image, the ink inside it is
+/// recolored with `color`
+#[derive(Debug, Clone)]
 pub struct Region {
     pub state: RegionState,
-    pub color: egui::Color32
+    pub color: egui::Color32,
+
+    /// How far from the key color a pixel can be to be considered ink
+    pub threshold: u8,
+
+    /// Color of the ink to extract, black when `None`
+    pub key_color: Option<egui::Color32>,
+
+    /// Name of the region, used as caption of its crop
+    pub name: String,
+
+    /// If the region is drawn over the image, hidden regions are still
+    /// cropped
+    pub visible: bool
+}
+
+/// What to do with incomplete regions, those missing their second corner,
+/// when the cropper is closed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IncompletePolicy {
+    /// Silently discard them
+    Drop,
+
+    /// Discard them printing a warning
+    Warn,
+
+    /// Ask the user whether to discard them or to keep drawing
+    Prompt,
+}
+
+/// Side or axis the selected regions are aligned to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Align {
+    Left,
+    Right,
+    Top,
+    Bottom,
+
+    /// Centers on the same vertical line
+    CenterX,
+
+    /// Centers on the same horizontal line
+    CenterY,
+}
+
+/// Restriction on the shape of the rectangles drawn
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Constraint {
+    /// Any size
+    Free,
+
+    /// Fixed ratio of the width to the height
+    Aspect(f32),
+
+    /// Fixed width and height, in pixels of the original image
+    Size(f32, f32),
+}
+
+impl Constraint {
+    /// Move the corner `(x2, y2)` of a rectangle anchored at `(x1, y1)` so
+    /// the rectangle satisfies the constraint, keeping the direction it was
+    /// dragged to
+    pub fn apply(&self, (x1, y1): (f32, f32), (x2, y2): (f32, f32)) -> (f32, f32) {
+        let (dx, dy) = (x2 - x1, y2 - y1);
+        let (width, height) = match *self {
+            Constraint::Free => return (x2, y2),
+            Constraint::Aspect(aspect) if aspect > 0.0 => {
+                // Shrink the side that is too long for the aspect
+                let (width, height) = (dx.abs(), dy.abs());
+                if height * aspect < width {
+                    (height * aspect, height)
+                } else {
+                    (width, width / aspect)
+                }
+            }
+            Constraint::Aspect(_) => return (x2, y2),
+            Constraint::Size(width, height) => (width, height),
+        };
+
+        (
+            x1 + width.copysign(if dx == 0.0 { 1.0 } else { dx }),
+            y1 + height.copysign(if dy == 0.0 { 1.0 } else { dy })
+        )
+    }
+}
+
+/// The crop of a region together with the region it was extracted from
+#[derive(Clone)]
+pub struct Crop {
+    pub region: Region,
+    pub image: Image
+}
+
+/// Distance from `(px, py)` to the segment from `a` to `b`
+fn segment_distance(
+    (px, py): (f32, f32),
+    (ax, ay): (f32, f32),
+    (bx, by): (f32, f32)
+) -> f32 {
+    let (dx, dy) = (bx - ax, by - ay);
+    let length = dx * dx + dy * dy;
+    let t = if length > 0.0 {
+        (((px - ax) * dx + (py - ay) * dy) / length).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    let (cx, cy) = (ax + t * dx, ay + t * dy);
+
+    ((px - cx).powi(2) + (py - cy).powi(2)).sqrt()
+}
+
+/// Even-odd test of `(px, py)` being inside the polygon of `points`
+fn polygon_contains(points: &[(f32, f32)], px: f32, py: f32) -> bool {
+    let mut inside = false;
+    let mut j = points.len().wrapping_sub(1);
+    for (i, &(xi, yi)) in points.iter().enumerate() {
+        let (xj, yj) = points[j];
+        if (yi > py) != (yj > py)
+                && px < (xj - xi) * (py - yi) / (yj - yi) + xi {
+            inside = !inside;
+        }
+        j = i;
+    }
+
+    inside
+}
+
+/// Bounds of `regions` together, `None` if none of them is complete
+fn union_bounds<'a>(
+    regions: impl IntoIterator<Item = &'a Region>
+) -> Option<(f32, f32, f32, f32)> {
+    regions.into_iter()
+        .filter_map(Region::bounds)
+        .reduce(|(ax1, ay1, ax2, ay2), (bx1, by1, bx2, by2)| {
+            (ax1.min(bx1), ay1.min(by1), ax2.max(bx2), ay2.max(by2))
+        })
 }
 
 impl Region {
-    fn start(x1: f32, y1: f32) -> Self {
+    /// Create a complete region from two opposite corners, in pixels of the
+    /// original image
+    pub fn new(
+        x1: f32, y1: f32,
+        x2: f32, y2: f32,
+        name: impl Into<String>
+    ) -> Self {
+        let mut region = Self::start(x1, y1, name.into());
+        region.finish(x2, y2, Constraint::Free, None);
+
+        region
+    }
+
+    /// Create a closed polygon region, in pixels of the original image
+    pub fn polygon(
+        points: Vec<(f32, f32)>,
+        name: impl Into<String>
+    ) -> Self {
+        Self {
+            state: RegionState::Polygon { points },
+            ..Self::start(0.0, 0.0, name.into())
+        }
+    }
+
+    pub fn with_color(mut self, color: egui::Color32) -> Self {
+        self.color = color;
+        self
+    }
+
+    /// Bounds of the region (x1, y1, x2, y2), the bounding box for polygons,
+    /// `None` if it's incomplete
+    pub fn bounds(&self) -> Option<(f32, f32, f32, f32)> {
+        match &self.state {
+            RegionState::Complete { x1, y1, x2, y2 } => {
+                Some((*x1, *y1, *x2, *y2))
+            }
+            RegionState::Polygon { points } if !points.is_empty() => {
+                Some(points.iter().fold(
+                    (f32::MAX, f32::MAX, f32::MIN, f32::MIN),
+                    |(x1, y1, x2, y2), &(x, y)| {
+                        (x1.min(x), y1.min(y), x2.max(x), y2.max(y))
+                    }))
+            }
+            _ => None
+        }
+    }
+
+    /// Size of the region (width, height), `None` if it's incomplete
+    pub fn size(&self) -> Option<(f32, f32)> {
+        self.bounds().map(|(x1, y1, x2, y2)| (x2 - x1, y2 - y1))
+    }
+
+    /// If the region is complete and both sides are at least `min_size`
+    pub fn is_valid(&self, min_size: f32) -> bool {
+        if let RegionState::Polygon { points } = &self.state {
+            if points.len() < 3 {
+                return false;
+            }
+        }
+
+        matches!(self.size(),
+            Some((width, height)) if width >= min_size && height >= min_size)
+    }
+
+    /// If the point is inside the region, incomplete regions contain nothing
+    pub fn contains(&self, px: f32, py: f32) -> bool {
+        match &self.state {
+            RegionState::Complete { x1, y1, x2, y2 } => {
+                px >= *x1 && px <= *x2 && py >= *y1 && py <= *y2
+            }
+            RegionState::Polygon { points } => {
+                polygon_contains(points, px, py)
+            }
+            _ => false
+        }
+    }
+
+    fn start(x1: f32, y1: f32, name: String) -> Self {
         Self { 
             state: RegionState::Start { 
                 x1,
                 y1
             },
-            color: egui::Color32::GRAY
+            color: egui::Color32::GRAY,
+            threshold: DEFAULT_THRESHOLD,
+            key_color: None,
+            name,
+            visible: true
         }
     }
 
-    fn finish(&mut self, mut x2: f32, mut y2: f32) {
-        match self.state {
-            RegionState::Start { mut x1, mut y1 } => {
-                if x1 > x2 {
-                    mem::swap(&mut x1, &mut x2);
+    /// Place the second corner, moved to satisfy `constraint` and, when
+    /// `image_size` is known, to keep the rectangle on the image
+    fn finish(
+        &mut self,
+        x2: f32, y2: f32,
+        constraint: Constraint,
+        image_size: Option<(f32, f32)>
+    ) {
+        if let RegionState::Start { mut x1, mut y1 } = self.state {
+            let (x2, y2) = match image_size {
+                Some((width, height)) => {
+                    (x2.clamp(0.0, width), y2.clamp(0.0, height))
                 }
-                if y1 > y2 {
-                    mem::swap(&mut y1, &mut y2);
+                None => (x2, y2)
+            };
+            let (mut x2, mut y2) = constraint.apply((x1, y1), (x2, y2));
+            if x1 > x2 {
+                mem::swap(&mut x1, &mut x2);
+            }
+            if y1 > y2 {
+                mem::swap(&mut y1, &mut y2);
+            }
+            self.state = RegionState::Complete { x1, y1, x2, y2 };
+            if let Some(image_size) = image_size {
+                self.shift_inside(image_size);
+            }
+        }
+    }
+
+    /// Move a rectangle back on an image of `(width, height)`, as far as its
+    /// top left corner stays on it
+    fn shift_inside(&mut self, (width, height): (f32, f32)) {
+        if let RegionState::Complete { x1, y1, x2, y2 } = self.state {
+            self.translate(
+                (width - x2).min(0.0).max(-x1),
+                (height - y2).min(0.0).max(-y1)
+            );
+        }
+    }
+
+    /// Move the region by (dx, dy), in pixels of the original image
+    pub fn translate(&mut self, dx: f32, dy: f32) {
+        match &mut self.state {
+            RegionState::Start { x1, y1 } => {
+                *x1 += dx;
+                *y1 += dy;
+            }
+            RegionState::Complete { x1, y1, x2, y2 } => {
+                *x1 += dx;
+                *y1 += dy;
+                *x2 += dx;
+                *y2 += dy;
+            }
+            RegionState::OpenPolygon { points }
+                    | RegionState::Polygon { points } => {
+                for (x, y) in points {
+                    *x += dx;
+                    *y += dy;
                 }
-                self.state = RegionState::Complete { x1, y1, x2, y2 };
             }
-            _ => panic!("This `Region` is already completed")
         }
     }
 
-    fn update_color(&mut self, color: egui::Color32) {
-        self.color = color;
+    /// Multiply the coordinates of the region by (sx, sy), to move it
+    /// between images of different sizes
+    pub fn scale(&mut self, sx: f32, sy: f32) {
+        match &mut self.state {
+            RegionState::Start { x1, y1 } => {
+                *x1 *= sx;
+                *y1 *= sy;
+            }
+            RegionState::Complete { x1, y1, x2, y2 } => {
+                *x1 *= sx;
+                *y1 *= sy;
+                *x2 *= sx;
+                *y2 *= sy;
+            }
+            RegionState::OpenPolygon { points }
+                    | RegionState::Polygon { points } => {
+                for (x, y) in points {
+                    *x *= sx;
+                    *y *= sy;
+                }
+            }
+        }
+    }
+
+    /// Shrink or grow a rectangle to the bounding box of the ink of `image`
+    /// found inside it extended by `margin` pixels, to absorb small shifts of
+    /// the layout, polygons are left as they are
+    ///
+    /// Returns if the region changed, it doesn't when there is no ink.
+    pub fn fit_to_content(&mut self, image: &Image, margin: f32) -> bool {
+        let RegionState::Complete { x1, y1, x2, y2 } = &mut self.state else {
+            return false;
+        };
+
+        match image.content_bounds(
+            (*x1 - margin, *y1 - margin, *x2 + margin, *y2 + margin),
+            self.key_color,
+            self.threshold
+        ) {
+            Some(bounds) => {
+                (*x1, *y1, *x2, *y2) = bounds;
+                true
+            }
+            None => false
+        }
+    }
+
+    /// Resize a rectangle to satisfy `constraint`, keeping its top left
+    /// corner and, for a fixed aspect, its width, polygons are left as they
+    /// are
+    ///
+    /// The rectangle is kept on an image of `(width, height)`, a fixed aspect
+    /// narrows it if it would grow past the bottom and a fixed size moves it.
+    pub fn constrain(&mut self, constraint: Constraint, (width, height): (u32, u32)) {
+        let (width, height) = (width as f32, height as f32);
+        if let RegionState::Complete { x1, y1, x2, y2 } = &mut self.state {
+            match constraint {
+                Constraint::Aspect(aspect) if aspect > 0.0 => {
+                    *y2 = *y1 + (*x2 - *x1) / aspect;
+                    if *y2 > height {
+                        *y2 = height.max(*y1);
+                        *x2 = *x1 + (*y2 - *y1) * aspect;
+                    }
+                }
+                constraint => {
+                    (*x2, *y2) = constraint.apply((*x1, *y1), (*x2, *y2));
+                }
+            }
+        }
+        self.shift_inside((width, height));
     }
 
-    fn path(&self) -> Option<Path> {
-        match self.state {
+    /// Outline of the region, scaled by `ratio` to window coordinates,
+    /// `None` if the region is incomplete or its bounds are not valid
+    fn path(&self, ratio: f32) -> Option<Path> {
+        match &self.state {
             RegionState::Complete { x1, y1, x2, y2 } => {
-                Some(PathBuilder::from_rect(
-                        Rect::from_ltrb(x1, y1, x2, y2).unwrap()))
+                Rect::from_ltrb(x1 * ratio, y1 * ratio, x2 * ratio, y2 * ratio)
+                    .map(PathBuilder::from_rect)
+            }
+            RegionState::Polygon { points } => {
+                let mut pb = PathBuilder::new();
+                let (x, y) = points.first()?;
+                pb.move_to(x * ratio, y * ratio);
+                for (x, y) in &points[1..] {
+                    pb.line_to(x * ratio, y * ratio);
+                }
+                pb.close();
+                pb.finish()
             }
             _ => None
         }
     }
 
-    fn collides(&self, px: f32, py: f32) -> Option<bool> {
-        for bline in self.blines(4.0)? {
+    fn collides(&self, px: f32, py: f32, margin: f32) -> Option<bool> {
+        if let RegionState::Polygon { points } = &self.state {
+            let edges = points.iter().zip(points.iter().cycle().skip(1));
+            for (a, b) in edges {
+                if segment_distance((px, py), *a, *b) <= margin {
+                    return Some(true);
+                }
+            }
+
+            return Some(false);
+        }
+
+        for bline in self.blines(margin)? {
             if bline.collides(px, py) {
                 return Some(true);
             }
         }
 
-        return Some(false);
+        Some(false)
     }
 
     fn blines(&self, margin: f32) -> Option<[BoundLine; 4]> {
@@ -170,116 +548,1083 @@ impl Region {
     }
 }
 
+/// Size of the text of the region labels, in pixels
+const LABEL_SIZE: f32 = 14.0;
+
+/// Space around the text of the region labels, in pixels
+const LABEL_PADDING: f32 = 3.0;
+
+/// What is drawn over the image
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Overlay {
+    /// If the regions are drawn at all, the region being drawn always is
+    pub visible: bool,
+
+    /// Opacity of the fill of the regions
+    pub fill_alpha: u8,
+
+    /// If the name of the regions is drawn next to them
+    pub labels: bool,
+
+    /// If the magnifier loupe follows the cursor
+    pub loupe: bool,
+}
+
+impl Default for Overlay {
+    fn default() -> Self {
+        Self {
+            visible: true,
+            fill_alpha: 48,
+            labels: true,
+            loupe: false
+        }
+    }
+}
+
+/// Offset of a duplicated region from the original, in pixels of the
+/// original image
+pub(crate) const DUPLICATE_OFFSET: f32 = 20.0;
+
+/// Default minimum size of the sides of a region, in pixels of the original
+/// image
+pub const DEFAULT_MIN_SIZE: f32 = 4.0;
+
+/// The set of regions drawn over an image, the order is the order of the
+/// crops
 pub struct Regions {
     regions: Vec<Region>,
-    selected_region: Option<usize>
+
+    /// Indices of the selected regions, the last one is the primary one,
+    /// whose properties are shown
+    selection: Vec<usize>,
+
+    /// Minimum size of the sides of a region to be kept
+    min_size: f32,
+
+    /// Number of regions created, used to name the new ones
+    created: usize,
+
+    /// Colors given to the new regions
+    scheme: Option<ColorScheme>,
+
+    /// Shape the new rectangles are restricted to
+    constraint: Constraint,
+
+    /// Index of the complete regions for hit testing, built when needed and
+    /// dropped by any change that may move a region
+    index: Option<GridIndex>,
+
+    /// Size of the image the regions are drawn on, when known the moves and
+    /// copies don't take the regions out of it
+    image_size: Option<(f32, f32)>,
+
+    /// Index of the region being drawn, kept on it as the regions are
+    /// removed or reordered
+    drawing: Option<usize>
+}
+
+impl Default for Regions {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Regions {
     pub fn new() -> Self {
         Self {
             regions: Vec::new(),
-            selected_region: None
+            selection: Vec::new(),
+            min_size: DEFAULT_MIN_SIZE,
+            created: 0,
+            scheme: None,
+            constraint: Constraint::Free,
+            index: None,
+            image_size: None,
+            drawing: None
         }
     }
 
-    pub fn start(&mut self, x1: f32, y1: f32) {
-        self.regions.push(Region::start(x1, y1));
+    /// Set the size of the image the regions are drawn on, see
+    /// [`Regions::move_selected`]
+    pub fn set_image_size(&mut self, (width, height): (u32, u32)) {
+        self.image_size = Some((width as f32, height as f32));
     }
 
-    pub fn finish(&mut self, x2: f32, y2: f32) {
-        if self.regions.is_empty() {
-            panic!("Can't finish regions because there is no region in regions");
+    /// The part of the move (dx, dy) of something with `bounds` that keeps
+    /// it on the image, what is already outside isn't pushed back in
+    fn clamp_move(
+        &self,
+        bounds: Option<(f32, f32, f32, f32)>,
+        dx: f32, dy: f32
+    ) -> (f32, f32) {
+        match (self.image_size, bounds) {
+            (Some((width, height)), Some((x1, y1, x2, y2))) => (
+                dx.min((width - x2).max(0.0)).max((-x1).min(0.0)),
+                dy.min((height - y2).max(0.0)).max((-y1).min(0.0))
+            ),
+            _ => (dx, dy)
         }
+    }
 
-        self.regions.last_mut().unwrap().finish(x2, y2);
+    /// Set the minimum size of the sides of a region, in pixels of the
+    /// original image, smaller regions are discarded
+    pub fn set_min_size(&mut self, min_size: f32) {
+        self.min_size = min_size.max(1.0);
     }
 
-    pub fn is_finished(&self) -> bool {
-        if self.regions.is_empty() {
-            true
-        } else {
-            if let Some(
-                RegionState::Complete { .. }
-            ) = self.regions.last().map(|r| r.state) {
+    pub fn push(&mut self, region: Region) {
+        self.index = None;
+        self.regions.push(region);
+        self.created += 1;
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Region> {
+        self.regions.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Region> {
+        self.index = None;
+        self.regions.iter_mut()
+    }
+
+    pub fn get(&self, idx: usize) -> Option<&Region> {
+        self.regions.get(idx)
+    }
+
+    pub fn get_mut(&mut self, idx: usize) -> Option<&mut Region> {
+        self.index = None;
+        self.regions.get_mut(idx)
+    }
+
+    /// Apply `edit` on the region at `idx`, the index for hit testing is
+    /// only dropped if its shape changed, unlike with [`Regions::get_mut`]
+    ///
+    /// Returns what `edit` returns, `None` if there is no such region.
+    pub fn edit<T>(
+        &mut self,
+        idx: usize,
+        edit: impl FnOnce(&mut Region) -> T
+    ) -> Option<T> {
+        let region = self.regions.get_mut(idx)?;
+        let before = region.state.clone();
+        let edited = edit(region);
+        if region.state != before {
+            self.index = None;
+        }
+
+        Some(edited)
+    }
+
+    pub fn len(&self) -> usize {
+        self.regions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.regions.is_empty()
+    }
+
+    /// Remove the region at `idx`, keeping the selection on the same regions
+    pub fn remove(&mut self, idx: usize) -> Region {
+        self.index = None;
+        self.selection.retain(|selected| *selected != idx);
+        for selected in &mut self.selection {
+            if *selected > idx {
+                *selected -= 1;
+            }
+        }
+        self.drawing = match self.drawing {
+            Some(drawing) if drawing == idx => None,
+            Some(drawing) if drawing > idx => Some(drawing - 1),
+            drawing => drawing
+        };
+
+        self.regions.remove(idx)
+    }
+
+    /// Remove every selected region
+    pub fn remove_selected(&mut self) {
+        let mut selection = mem::take(&mut self.selection);
+        selection.sort_unstable();
+        for idx in selection.into_iter().rev() {
+            self.remove(idx);
+        }
+    }
+
+    /// Append a copy of each of `regions` moved by (dx, dy), named after
+    /// them, and select the copies, the region being drawn is discarded
+    ///
+    /// The move is shortened so the copies stay on the image.
+    pub fn paste(&mut self, regions: &[Region], dx: f32, dy: f32) {
+        self.cancel();
+        self.selection.clear();
+        let (dx, dy) = self.clamp_move(union_bounds(regions), dx, dy);
+        for region in regions {
+            let mut copy = region.clone();
+            copy.translate(dx, dy);
+            copy.name = format!("{} copy", region.name);
+            self.push(copy);
+            self.selection.push(self.regions.len() - 1);
+        }
+    }
+
+    /// Clones of the selected regions, in selection order
+    pub fn selected_regions(&self) -> Vec<Region> {
+        self.selection.iter()
+            .map(|idx| self.regions[*idx].clone())
+            .collect()
+    }
+
+    /// Fill a grid of `rows` x `columns` cells with copies of the region at
+    /// `idx`, which is the top left cell, leaving `spacing` between the
+    /// cells
+    ///
+    /// The copies are inserted after the region in reading order, so the
+    /// crops keep that order, and are named after their cell, the cells that
+    /// don't fit on the image are left out
+    pub fn replicate(
+        &mut self,
+        idx: usize,
+        rows: u32, columns: u32,
+        (spacing_x, spacing_y): (f32, f32)
+    ) {
+        let original = match self.regions.get(idx) {
+            Some(region) => region.clone(),
+            None => return
+        };
+        let (width, height) = match original.size() {
+            Some(size) => size,
+            None => return
+        };
+
+        let mut copies = Vec::new();
+        for row in 0..rows {
+            for column in 0..columns {
+                if row == 0 && column == 0 {
+                    continue;
+                }
+
+                let (dx, dy) = (
+                    column as f32 * (width + spacing_x),
+                    row as f32 * (height + spacing_y)
+                );
+                if self.clamp_move(original.bounds(), dx, dy) != (dx, dy) {
+                    continue;
+                }
+
+                let mut copy = original.clone();
+                copy.translate(dx, dy);
+                copy.name = format!("{} ({}, {})",
+                    original.name, row + 1, column + 1);
+                copies.push(copy);
+            }
+        }
+
+        let count = copies.len();
+        self.created += count;
+        self.index = None;
+        self.regions.splice(idx + 1..idx + 1, copies);
+        for selected in self.selection.iter_mut().chain(&mut self.drawing) {
+            if *selected > idx {
+                *selected += count;
+            }
+        }
+    }
+
+    /// Swap the order of two regions, keeping the selection on the same
+    /// regions
+    pub fn swap(&mut self, a: usize, b: usize) {
+        self.index = None;
+        self.regions.swap(a, b);
+        for selected in self.selection.iter_mut().chain(&mut self.drawing) {
+            if *selected == a {
+                *selected = b;
+            } else if *selected == b {
+                *selected = a;
+            }
+        }
+    }
+
+    /// Set the color scheme the new regions are colored with, without one
+    /// they start gray
+    pub fn set_scheme(&mut self, scheme: Option<ColorScheme>) {
+        self.scheme = scheme;
+    }
+
+    pub fn scheme(&self) -> Option<&ColorScheme> {
+        self.scheme.as_ref()
+    }
+
+    /// Color every region with the color of its position on `scheme`
+    pub fn recolor(&mut self, scheme: &ColorScheme) {
+        for (idx, region) in self.regions.iter_mut().enumerate() {
+            region.color = scheme.color(idx);
+        }
+    }
+
+    /// Restrict the shape of the new rectangles, and of the ones resized
+    pub fn set_constraint(&mut self, constraint: Constraint) {
+        self.constraint = constraint;
+    }
+
+    pub fn constraint(&self) -> Constraint {
+        self.constraint
+    }
+
+    /// Make the new rectangles the size of the selected region
+    ///
+    /// Returns if there was a complete region selected
+    pub fn match_selected_size(&mut self) -> bool {
+        match self.selected().and_then(Region::size) {
+            Some((width, height)) => {
+                self.constraint = Constraint::Size(width, height);
                 true
+            }
+            None => false
+        }
+    }
+
+    /// Region to append starting at (x1, y1), with a unique name and the
+    /// next color of the scheme
+    fn next_region(&mut self, x1: f32, y1: f32) -> Region {
+        self.created += 1;
+        let color = self.scheme.as_ref()
+            .map_or(egui::Color32::GRAY, |scheme| {
+                scheme.color(self.regions.len())
+            });
+
+        Region::start(x1, y1, format!("Region {}", self.created))
+            .with_color(color)
+    }
+
+    pub(crate) fn start(&mut self, x1: f32, y1: f32) {
+        let region = self.next_region(x1, y1);
+        self.regions.push(region);
+        self.drawing = Some(self.regions.len() - 1);
+    }
+
+    /// Place the second corner of the region being drawn, if the region ends
+    /// up smaller than the minimum size it's discarded, so clicking twice on
+    /// the same spot cancels it
+    pub(crate) fn finish(&mut self, x2: f32, y2: f32) {
+        self.index = None;
+        if let Some(idx) = self.drawing.take() {
+            let region = &mut self.regions[idx];
+            region.finish(x2, y2, self.constraint, self.image_size);
+            if !region.is_valid(self.min_size) {
+                self.remove(idx);
+            }
+        }
+    }
+
+    /// Add a point to the polygon being drawn, or start a new one, clicking
+    /// at a distance lower than `close_margin` of the first point closes it
+    pub(crate) fn add_polygon_point(
+        &mut self,
+        x: f32, y: f32,
+        close_margin: f32
+    ) {
+        if let Some(Region {
+            state: RegionState::OpenPolygon { points },
+            ..
+        }) = self.drawing.map(|idx| &mut self.regions[idx]) {
+            let closes = points.len() >= 3 && segment_distance(
+                (x, y), points[0], points[0]) <= close_margin;
+            if closes {
+                self.close_polygon();
             } else {
-                false
+                points.push((x, y));
             }
+            return;
+        }
+
+        let region = Region {
+            state: RegionState::OpenPolygon { points: vec![(x, y)] },
+            ..self.next_region(x, y)
+        };
+        self.regions.push(region);
+        self.drawing = Some(self.regions.len() - 1);
+    }
+
+    /// Close the polygon being drawn, it's discarded if it has less than
+    /// three points or is smaller than the minimum size
+    pub(crate) fn close_polygon(&mut self) {
+        self.index = None;
+        let Some(idx) = self.drawing else {
+            return;
+        };
+        let region = &mut self.regions[idx];
+        if let RegionState::OpenPolygon { points } = &mut region.state {
+            region.state = RegionState::Polygon {
+                points: mem::take(points)
+            };
+            self.drawing = None;
+            if !region.is_valid(self.min_size) {
+                self.remove(idx);
+            }
+        }
+    }
+
+    /// Discard the region being drawn, if any
+    pub(crate) fn cancel(&mut self) {
+        if let Some(idx) = self.drawing {
+            self.remove(idx);
+        }
+    }
+
+    pub(crate) fn is_finished(&self) -> bool {
+        self.drawing.is_none()
+    }
+
+    /// Remove the regions that can't be exported, the incomplete ones and the
+    /// ones smaller than the minimum size
+    ///
+    /// Returns the removed regions
+    pub fn validate(&mut self) -> Vec<Region> {
+        let min_size = self.min_size;
+        let (valid, invalid) = mem::take(&mut self.regions)
+            .into_iter()
+            .partition(|region| region.is_valid(min_size));
+        self.regions = valid;
+        self.selection.clear();
+        self.index = None;
+        self.drawing = None;
+
+        invalid
+    }
+
+    /// Regions under the point, inside them or at a distance lower than
+    /// `margin` of their borders, the topmost first, which is the last one
+    /// drawn
+    pub(crate) fn regions_at(
+        &mut self,
+        px: f32, py: f32,
+        margin: f32
+    ) -> Vec<usize> {
+        let regions = &self.regions;
+        let index = self.index.get_or_insert_with(|| {
+            GridIndex::build(regions.iter()
+                .enumerate()
+                .filter(|(_, region)| region.state.is_complete())
+                .filter_map(|(idx, region)| Some((idx, region.bounds()?))))
+        });
+
+        let mut hits: Vec<usize> = index.query(px, py, margin)
+            .into_iter()
+            .filter(|idx| {
+                let region = &regions[*idx];
+                region.contains(px, py)
+                    || region.collides(px, py, margin) == Some(true)
+            })
+            .collect();
+        hits.reverse();
+
+        hits
+    }
+
+    /// Region to select under the point, the topmost one, or when `cycle`
+    /// the one below the primary selected region, so repeated clicks on the
+    /// same spot go through every overlapping region
+    pub(crate) fn region_at(
+        &mut self,
+        px: f32, py: f32,
+        margin: f32,
+        cycle: bool
+    ) -> Option<usize> {
+        let hits = self.regions_at(px, py, margin);
+        let current = self.selected_index()
+            .and_then(|selected| hits.iter().position(|hit| *hit == selected));
+
+        match current {
+            Some(position) if cycle => Some(hits[(position + 1) % hits.len()]),
+            // Keep the selection so it can be dragged
+            Some(position) => Some(hits[position]),
+            None => hits.first().copied()
         }
     }
 
-    /// Try to select the first region found that is collided by the mouse.
+    /// Try to select the region under the point, cycling through the
+    /// overlapping ones if `cycle`
     ///
     /// Returns if it found any.
-    pub fn select_collided_region(
+    pub(crate) fn select_at(
         &mut self,
-        px: f32, py: f32
+        px: f32, py: f32,
+        margin: f32,
+        cycle: bool
     ) -> bool {
-        for (idx, region) in self.regions.iter().enumerate() {
-            dbg!(region, &region.collides(px, py));
-            if let Some(true) = region.collides(px, py) {
-                self.selected_region = Some(idx);
-                return true;
+        match self.region_at(px, py, margin, cycle) {
+            Some(idx) => {
+                self.select(idx);
+                true
             }
+            None => false
+        }
+    }
+
+    /// Index of the primary selected region
+    pub fn selected_index(&self) -> Option<usize> {
+        self.selection.last().copied()
+    }
+
+    /// Indices of every selected region
+    pub fn selection(&self) -> &[usize] {
+        &self.selection
+    }
+
+    pub fn is_selected(&self, idx: usize) -> bool {
+        self.selection.contains(&idx)
+    }
+
+    /// Select only the region at `idx`
+    pub fn select(&mut self, idx: usize) {
+        if idx < self.regions.len() {
+            self.selection = vec![idx];
+        }
+    }
+
+    /// Add the region at `idx` to the selection, as the primary one, or
+    /// remove it if it was already selected
+    pub fn toggle_selection(&mut self, idx: usize) {
+        if self.is_selected(idx) {
+            self.selection.retain(|selected| *selected != idx);
+        } else if idx < self.regions.len() {
+            self.selection.push(idx);
         }
+    }
 
-        return false;
+    pub fn select_all(&mut self) {
+        self.selection = (0..self.regions.len())
+            .filter(|idx| self.regions[*idx].state.is_complete())
+            .collect();
     }
 
-    pub fn update_selected_color(&mut self, color: egui::Color32) {
-        if let Some(idx) = self.selected_region {
-            self.regions[idx].update_color(color);
+    /// Select the regions fully inside the rectangle between two corners,
+    /// adding them to the current selection if `add`
+    pub fn select_in_rect(
+        &mut self,
+        (ax, ay): (f32, f32),
+        (bx, by): (f32, f32),
+        add: bool
+    ) {
+        if !add {
+            self.selection.clear();
+        }
+        let (x1, y1, x2, y2) = (ax.min(bx), ay.min(by), ax.max(bx), ay.max(by));
+        for (idx, region) in self.regions.iter().enumerate() {
+            let inside = matches!(region.bounds(), Some((rx1, ry1, rx2, ry2))
+                if rx1 >= x1 && ry1 >= y1 && rx2 <= x2 && ry2 <= y2);
+            if inside && !self.selection.contains(&idx) {
+                self.selection.push(idx);
+            }
         }
     }
 
+    pub fn selected(&self) -> Option<&Region> {
+        self.selected_index().map(|idx| &self.regions[idx])
+    }
+
+    pub fn selected_mut(&mut self) -> Option<&mut Region> {
+        self.index = None;
+        self.selected_index().map(|idx| &mut self.regions[idx])
+    }
+
     pub fn deselect(&mut self) {
-        assert!(self.selected_region.is_some());
-        self.selected_region = None;
+        self.selection.clear();
+    }
+
+    /// Apply `edit` on every selected region, see [`Regions::edit`]
+    pub fn edit_selected(&mut self, mut edit: impl FnMut(&mut Region)) {
+        for idx in &self.selection {
+            let region = &mut self.regions[*idx];
+            let before = region.state.clone();
+            edit(region);
+            if region.state != before {
+                self.index = None;
+            }
+        }
     }
 
-    pub fn render(&self, pixmap: &mut PixmapMut) {
-        // Create paint color and stroke
+    /// Fit the selected rectangles to the ink around them, see
+    /// [`Region::fit_to_content`]
+    pub fn fit_selected_to_content(&mut self, image: &Image, margin: f32) {
+        self.edit_selected(|region| {
+            region.fit_to_content(image, margin);
+        });
+    }
+
+    /// Move every selected region by (dx, dy), as far as they stay on the
+    /// image if its size is known
+    ///
+    /// Returns the move done.
+    pub fn move_selected(&mut self, dx: f32, dy: f32) -> (f32, f32) {
+        let (dx, dy) = self.clamp_move(self.selection_bounds(), dx, dy);
+        self.edit_selected(|region| region.translate(dx, dy));
+
+        (dx, dy)
+    }
+
+    /// Bounds of the selected regions together
+    fn selection_bounds(&self) -> Option<(f32, f32, f32, f32)> {
+        union_bounds(self.selection.iter().map(|idx| &self.regions[*idx]))
+    }
+
+    /// Align the selected regions to the bounds of the whole selection
+    pub fn align_selected(&mut self, align: Align) {
+        let (sx1, sy1, sx2, sy2) = match self.selection_bounds() {
+            Some(bounds) => bounds,
+            None => return
+        };
+        self.edit_selected(|region| {
+            let (x1, y1, x2, y2) = match region.bounds() {
+                Some(bounds) => bounds,
+                None => return
+            };
+            let (dx, dy) = match align {
+                Align::Left => (sx1 - x1, 0.0),
+                Align::Right => (sx2 - x2, 0.0),
+                Align::Top => (0.0, sy1 - y1),
+                Align::Bottom => (0.0, sy2 - y2),
+                Align::CenterX => ((sx1 + sx2 - x1 - x2) / 2.0, 0.0),
+                Align::CenterY => (0.0, (sy1 + sy2 - y1 - y2) / 2.0),
+            };
+            region.translate(dx, dy);
+        });
+    }
+
+    /// Move the selected regions so the gaps between them along an axis are
+    /// equal, the first and last ones stay in place
+    pub fn distribute_selected(&mut self, horizontally: bool) {
+        let mut items: Vec<(usize, f32, f32)> = self.selection.iter()
+            .filter_map(|idx| {
+                let (x1, y1, x2, y2) = self.regions[*idx].bounds()?;
+                Some(if horizontally {
+                    (*idx, x1, x2 - x1)
+                } else {
+                    (*idx, y1, y2 - y1)
+                })
+            })
+            .collect();
+        if items.len() < 3 {
+            return;
+        }
+        items.sort_by(|a, b| a.1.total_cmp(&b.1));
+        self.index = None;
+
+        let (_, start, _) = items[0];
+        let (_, last, last_size) = items[items.len() - 1];
+        let sizes: f32 = items.iter().map(|(_, _, size)| size).sum();
+        let gap = (last + last_size - start - sizes) / (items.len() - 1) as f32;
+        let mut position = start;
+        for (idx, current, size) in items {
+            let delta = position - current;
+            if horizontally {
+                self.regions[idx].translate(delta, 0.0);
+            } else {
+                self.regions[idx].translate(0.0, delta);
+            }
+            position += size + gap;
+        }
+    }
+
+    /// Draw the regions, scaled by `ratio` to window coordinates, the region
+    /// being drawn is previewed as if it was finished at `cursor`
+    pub(crate) fn render(
+        &self,
+        pixmap: &mut PixmapMut,
+        ratio: f32,
+        cursor: (f32, f32),
+        overlay: &Overlay
+    ) {
         let mut paint = Paint::default();
-        paint.set_color_rgba8(50, 127, 150, 255);
-        let mut stroke = Stroke::default();
-        stroke.width = 4.0;
+        let stroke = Stroke {
+            width: 2.0,
+            ..Stroke::default()
+        };
+        let selected_stroke = Stroke {
+            width: 3.0,
+            dash: StrokeDash::new(vec![8.0, 4.0], 0.0),
+            ..Stroke::default()
+        };
+
+        let mut labels = Vec::new();
+        for (idx, region) in self.regions.iter().enumerate() {
+            let color = region.color;
+            paint.set_color_rgba8(color.r(), color.g(), color.b(), 255);
+
+            // The region being drawn is always shown, it's the feedback of
+            // the current tool
+            match &region.state {
+                RegionState::Start { x1, y1 } => {
+                    let (cx, cy) = self.constraint.apply(
+                        (*x1, *y1),
+                        (cursor.0 / ratio, cursor.1 / ratio)
+                    );
+                    Self::render_rubber_band(
+                        pixmap,
+                        &paint,
+                        (x1 * ratio, y1 * ratio),
+                        (cx * ratio, cy * ratio)
+                    );
+                    continue;
+                }
+                RegionState::OpenPolygon { points } => {
+                    Self::render_open_polygon(
+                        pixmap, &paint, points, ratio, cursor);
+                    continue;
+                }
+                _ => {}
+            }
 
-        // Draw every region rect
-        for region in &self.regions {
-            let path = region.path();
-            if path.is_none() {
-                break;
+            if !overlay.visible || !region.visible {
+                continue;
             }
-            let path = path.unwrap();
+            let path = match region.path(ratio) {
+                Some(path) => path,
+                None => continue
+            };
 
-            // Use the color of the region
+            // Semi-transparent fill, so the ink underneath stays visible
             paint.set_color_rgba8(
-                region.color.r(),
-                region.color.g(),
-                region.color.b(),
-                region.color.a()
+                color.r(), color.g(), color.b(), overlay.fill_alpha);
+            pixmap.fill_path(
+                &path,
+                &paint,
+                FillRule::Winding,
+                Transform::identity(),
+                None
             );
 
+            paint.set_color_rgba8(color.r(), color.g(), color.b(), 255);
+            let stroke = if self.is_selected(idx) {
+                &selected_stroke
+            } else {
+                &stroke
+            };
             pixmap.stroke_path(
                 &path,
                 &paint,
-                &stroke,
+                stroke,
+                Transform::identity(),
+                None
+            );
+
+            if overlay.labels {
+                labels.push(region);
+            }
+        }
+
+        // Labels go last so no region is drawn over them
+        for region in labels {
+            Self::render_label(pixmap, region, ratio);
+        }
+    }
+
+    /// Draw the name of `region` on a tag of its color over its top left
+    /// corner, or inside it if there is no room above
+    fn render_label(pixmap: &mut PixmapMut, region: &Region, ratio: f32) {
+        let (x1, y1, _, _) = match region.bounds() {
+            Some(bounds) => bounds,
+            None => return
+        };
+        let (width, height) = text::measure(&region.name, LABEL_SIZE);
+        let (width, height) = (width + 2.0 * LABEL_PADDING, height);
+        let x = x1 * ratio;
+        let y = if y1 * ratio >= height { y1 * ratio - height } else { y1 * ratio };
+
+        let color = region.color;
+        let mut paint = Paint::default();
+        paint.set_color_rgba8(color.r(), color.g(), color.b(), 220);
+        if let Some(rect) = Rect::from_xywh(x, y, width, height) {
+            pixmap.fill_rect(rect, &paint, Transform::identity(), None);
+        }
+
+        // Dark text over light colors and light text over dark ones
+        let luma = 0.299 * color.r() as f32
+            + 0.587 * color.g() as f32
+            + 0.114 * color.b() as f32;
+        let text_color = if luma > 140.0 {
+            Rgba([0, 0, 0, 255])
+        } else {
+            Rgba([255, 255, 255, 255])
+        };
+
+        text::draw_on_pixmap(
+            pixmap,
+            &region.name,
+            x + LABEL_PADDING, y,
+            LABEL_SIZE,
+            text_color
+        );
+    }
+
+    /// Stroke used to preview the region being drawn
+    fn rubber_band_stroke() -> Stroke {
+        Stroke {
+            width: 2.0,
+            dash: StrokeDash::new(vec![6.0, 4.0], 0.0),
+            ..Stroke::default()
+        }
+    }
+
+    /// Draw the rectangle that the region being drawn would have if it was
+    /// finished at `cursor`
+    fn render_rubber_band(
+        pixmap: &mut PixmapMut,
+        paint: &Paint,
+        (x1, y1): (f32, f32),
+        (cx, cy): (f32, f32)
+    ) {
+        if let Some(rect) = Rect::from_ltrb(
+            x1.min(cx), y1.min(cy),
+            x1.max(cx), y1.max(cy)
+        ) {
+            pixmap.stroke_path(
+                &PathBuilder::from_rect(rect),
+                paint,
+                &Self::rubber_band_stroke(),
                 Transform::identity(),
                 None
             );
         }
     }
 
-    pub fn get_image_crops(&self, ratio: f32, original_image: &Image) -> Vec<Image> {
+    /// Draw the placed points of the polygon being drawn, joined up to
+    /// `cursor`
+    fn render_open_polygon(
+        pixmap: &mut PixmapMut,
+        paint: &Paint,
+        points: &[(f32, f32)],
+        ratio: f32,
+        (cx, cy): (f32, f32)
+    ) {
+        let mut pb = PathBuilder::new();
+        for (idx, (x, y)) in points.iter().enumerate() {
+            if idx == 0 {
+                pb.move_to(x * ratio, y * ratio);
+            } else {
+                pb.line_to(x * ratio, y * ratio);
+            }
+        }
+        pb.line_to(cx, cy);
+
+        if let Some(path) = pb.finish() {
+            pixmap.stroke_path(
+                &path,
+                paint,
+                &Self::rubber_band_stroke(),
+                Transform::identity(),
+                None
+            );
+        }
+    }
+
+    /// Extract the crop of every region from the image they were drawn on,
+    /// doesn't require a window so it can be used to crop headlessly
+    ///
+    /// Fails if any region is incomplete or has no area, the regions
+    /// entirely outside the image are skipped with a warning
+    pub fn get_image_crops(&self, original_image: &Image) -> Result<Vec<Crop>> {
         let mut res = Vec::new();
-        let mut c = 0;
-        for region in &self.regions {
-            res.push(original_image.extract_region(c, ratio, *region));
-            c += 1;
+        for (c, region) in self.regions.iter().enumerate() {
+            let image = match original_image.extract_region(c as u32, region) {
+                Ok(image) => image,
+                Err(Error::OutsideImage(name)) => {
+                    eprintln!("Skipping region `{}`, it's outside the image",
+                        name);
+                    continue;
+                }
+                Err(err) => return Err(err)
+            };
+            res.push(Crop {
+                region: region.clone(),
+                image
+            });
         }
 
-        res
+        Ok(res)
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edits_keep_the_index_unless_the_shape_changes() {
+        let mut regions = Regions::new();
+        regions.push(Region::new(10.0, 10.0, 50.0, 50.0, "a"));
+        regions.push(Region::new(100.0, 10.0, 150.0, 50.0, "b"));
+        regions.select(1);
+        assert_eq!(regions.regions_at(20.0, 20.0, 0.0), [0]);
+        assert!(regions.index.is_some());
+
+        regions.edit(0, |region| {
+            region.name = "renamed".to_owned();
+            region.visible = false;
+        });
+        regions.edit_selected(|region| region.threshold = 100);
+        assert!(regions.index.is_some());
+
+        regions.edit(0, |region| region.translate(100.0, 0.0));
+        assert!(regions.index.is_none());
+        assert_eq!(regions.regions_at(120.0, 20.0, 0.0), [1, 0]);
+        regions.edit_selected(|region| region.translate(0.0, 100.0));
+        assert!(regions.index.is_none());
+        assert_eq!(regions.regions_at(120.0, 20.0, 0.0), [0]);
+    }
+
+    #[test]
+    fn drawn_constraints_stay_on_the_image() {
+        let mut regions = Regions::new();
+        regions.set_image_size((200, 100));
+
+        // Dragged past the corner, the aspect is taken inside the image
+        regions.set_constraint(Constraint::Aspect(2.0));
+        regions.start(150.0, 60.0);
+        regions.finish(250.0, 130.0);
+        assert_eq!(regions.get(0).and_then(Region::bounds),
+            Some((150.0, 60.0, 200.0, 85.0)));
+
+        // A fixed size that doesn't fit is moved back on the image
+        regions.set_constraint(Constraint::Size(80.0, 60.0));
+        regions.start(150.0, 60.0);
+        regions.finish(160.0, 70.0);
+        assert_eq!(regions.get(1).and_then(Region::bounds),
+            Some((120.0, 40.0, 200.0, 100.0)));
+        regions.start(30.0, 20.0);
+        regions.finish(10.0, 10.0);
+        assert_eq!(regions.get(2).and_then(Region::bounds),
+            Some((0.0, 0.0, 80.0, 60.0)));
+    }
+
+    #[test]
+    fn edited_constraints_stay_on_the_image() {
+        let mut region = Region::new(20.0, 50.0, 120.0, 90.0, "a");
+        region.constrain(Constraint::Aspect(1.0), (200, 100));
+        assert_eq!(region.bounds(), Some((20.0, 50.0, 70.0, 100.0)));
+
+        region.constrain(Constraint::Aspect(2.0), (200, 100));
+        assert_eq!(region.bounds(), Some((20.0, 50.0, 70.0, 75.0)));
+
+        let mut region = Region::new(150.0, 80.0, 160.0, 90.0, "b");
+        region.constrain(Constraint::Size(80.0, 60.0), (200, 100));
+        assert_eq!(region.bounds(), Some((120.0, 40.0, 200.0, 100.0)));
+    }
+
+    #[test]
+    fn replicate_spaces_the_copies() {
+        let mut regions = Regions::new();
+        regions.push(Region::new(10.0, 10.0, 60.0, 40.0, "a"));
+        regions.push(Region::new(500.0, 500.0, 600.0, 600.0, "b"));
+        regions.select(1);
+
+        regions.replicate(0, 2, 3, (5.0, 10.0));
+        let bounds: Vec<_> = regions.iter().filter_map(Region::bounds).collect();
+        assert_eq!(bounds, [
+            (10.0, 10.0, 60.0, 40.0),
+            (65.0, 10.0, 115.0, 40.0),
+            (120.0, 10.0, 170.0, 40.0),
+            (10.0, 50.0, 60.0, 80.0),
+            (65.0, 50.0, 115.0, 80.0),
+            (120.0, 50.0, 170.0, 80.0),
+            (500.0, 500.0, 600.0, 600.0),
+        ]);
+        assert_eq!(regions.get(5).unwrap().name, "a (2, 3)");
+        assert_eq!(regions.selection(), [6]);
+    }
+
+    #[test]
+    fn replicate_leaves_out_the_cells_off_the_image() {
+        let mut regions = Regions::new();
+        regions.set_image_size((150, 70));
+        regions.push(Region::new(10.0, 10.0, 60.0, 40.0, "a"));
+
+        regions.replicate(0, 2, 3, (5.0, 10.0));
+        let names: Vec<&str> = regions.iter()
+            .map(|region| region.name.as_str())
+            .collect();
+        assert_eq!(names, ["a", "a (1, 2)"]);
+    }
+
+    #[test]
+    fn paste_stops_at_the_border() {
+        let mut regions = Regions::new();
+        regions.set_image_size((200, 100));
+        let copied = [
+            Region::new(100.0, 40.0, 150.0, 70.0, "a"),
+            Region::new(150.0, 60.0, 190.0, 90.0, "b"),
+        ];
+
+        regions.paste(&copied, 20.0, 20.0);
+        let bounds: Vec<_> = regions.iter().filter_map(Region::bounds).collect();
+        assert_eq!(bounds, [(110.0, 50.0, 160.0, 80.0), (160.0, 70.0, 200.0, 100.0)]);
+        assert_eq!(regions.get(1).unwrap().name, "b copy");
+        assert_eq!(regions.selection(), [0, 1]);
+
+        // Already on the border, the copies only move away from it
+        regions.paste(&regions.selected_regions(), 20.0, -20.0);
+        let bounds: Vec<_> = regions.iter()
+            .skip(2)
+            .filter_map(Region::bounds)
+            .collect();
+        assert_eq!(bounds, [(110.0, 30.0, 160.0, 60.0), (160.0, 50.0, 200.0, 80.0)]);
+    }
+
+    #[test]
+    fn distribute_needs_three_regions() {
+        let mut regions = Regions::new();
+        regions.push(Region::new(0.0, 0.0, 10.0, 10.0, "a"));
+        regions.push(Region::new(15.0, 0.0, 35.0, 10.0, "b"));
+        regions.select_all();
+
+        regions.distribute_selected(true);
+        let bounds: Vec<_> = regions.iter().filter_map(Region::bounds).collect();
+        assert_eq!(bounds, [(0.0, 0.0, 10.0, 10.0), (15.0, 0.0, 35.0, 10.0)]);
+    }
+
+    #[test]
+    fn distribute_evens_the_gaps() {
+        let mut regions = Regions::new();
+        regions.push(Region::new(90.0, 0.0, 100.0, 10.0, "last"));
+        regions.push(Region::new(15.0, 5.0, 25.0, 15.0, "second"));
+        regions.push(Region::new(0.0, 90.0, 10.0, 100.0, "first"));
+        regions.push(Region::new(20.0, 30.0, 30.0, 40.0, "third"));
+        regions.push(Region::new(200.0, 0.0, 210.0, 10.0, "unselected"));
+        for idx in 0..4 {
+            regions.toggle_selection(idx);
+        }
+
+        // The sizes add up to 40 of the 100 spanned, leaving gaps of 20
+        regions.distribute_selected(true);
+        let x: Vec<f32> = regions.iter()
+            .filter_map(Region::bounds)
+            .map(|(x1, ..)| x1)
+            .collect();
+        assert_eq!(x, [90.0, 30.0, 0.0, 60.0, 200.0]);
+
+        // Vertically the first and last are the ones on top and bottom
+        regions.distribute_selected(false);
+        let y: Vec<f32> = regions.iter()
+            .filter_map(Region::bounds)
+            .map(|(_, y1, ..)| y1)
+            .collect();
+        assert_eq!(y, [0.0, 30.0, 90.0, 60.0, 0.0]);
+    }
+
+    #[test]
+    fn concave_polygons_dont_contain_their_notch() {
+        // A U, open at the bottom
+        let region = Region::polygon(vec![
+            (0.0, 0.0), (30.0, 0.0), (30.0, 30.0), (20.0, 30.0),
+            (20.0, 10.0), (10.0, 10.0), (10.0, 30.0), (0.0, 30.0),
+        ], "u");
+
+        assert!(region.contains(5.0, 20.0));
+        assert!(region.contains(25.0, 20.0));
+        assert!(region.contains(15.0, 5.0));
+        assert!(!region.contains(15.0, 20.0));
+        assert!(!region.contains(15.0, 35.0));
+        assert!(!region.contains(40.0, 5.0));
+        assert_eq!(region.bounds(), Some((0.0, 0.0, 30.0, 30.0)));
+    }
+}
@@ -0,0 +1,341 @@
+use image::imageops::{self, FilterType};
+use image::{GrayImage, Luma};
+
+use crate::image::Image;
+
+/// Longest side of the reference image stored with a template, in pixels
+pub(crate) const REFERENCE_SIZE: u32 = 512;
+
+/// Longest side of the coarsest level of the search, in pixels
+const COARSE_SIZE: u32 = 128;
+
+/// Most edge points of the reference compared on each level
+const MAX_POINTS: usize = 1500;
+
+/// Largest shift searched, relative to the side of the page
+const MAX_SHIFT: f32 = 0.1;
+
+/// Largest change of scale searched
+const MAX_SCALE: f32 = 0.08;
+
+/// Largest rotation searched, in degrees
+const MAX_ANGLE: f32 = 3.0;
+
+/// Lowest score of an alignment to be trusted, below it the page likely
+/// doesn't have the layout of the reference
+const MIN_SCORE: f32 = 0.1;
+
+/// How much better than leaving the page as it is an alignment has to
+/// score to be used, relative to the score of the page as it is
+const MIN_GAIN: f32 = 0.05;
+
+/// Transform that takes a point of the reference page to the same point of
+/// another page, in coordinates relative to the size of the pages
+///
+/// The page is scaled and rotated around its center and then shifted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Alignment {
+    pub dx: f32,
+    pub dy: f32,
+    pub scale: f32,
+
+    /// Rotation, in degrees
+    pub angle: f32,
+
+    /// How well the edges match with this transform, from 0 to 1
+    pub score: f32,
+}
+
+impl Default for Alignment {
+    fn default() -> Self {
+        Self {
+            dx: 0.0,
+            dy: 0.0,
+            scale: 1.0,
+            angle: 0.0,
+            score: 0.0
+        }
+    }
+}
+
+impl Alignment {
+    /// Transform a point relative to a page of `aspect` width to height, the
+    /// rotation is done in pixels so it stays a rotation on pages that aren't
+    /// square
+    pub fn apply(&self, (x, y): (f32, f32), aspect: f32) -> (f32, f32) {
+        let (sin, cos) = self.angle.to_radians().sin_cos();
+        let (cx, cy) = ((x - 0.5) * aspect, y - 0.5);
+        let (rx, ry) = (cos * cx - sin * cy, sin * cx + cos * cy);
+
+        (
+            rx * self.scale / aspect + 0.5 + self.dx,
+            ry * self.scale + 0.5 + self.dy
+        )
+    }
+}
+
+/// Grayscale version of `image` with its longest side scaled to `size`, to
+/// be stored as the reference of a template
+pub(crate) fn reference(image: &Image, size: u32) -> GrayImage {
+    let (width, height) = image.dimensions();
+    let ratio = size as f32 / width.max(height) as f32;
+    let gray = imageops::grayscale(image.buffer());
+
+    imageops::resize(
+        &gray,
+        ((width as f32 * ratio) as u32).max(1),
+        ((height as f32 * ratio) as u32).max(1),
+        FilterType::Triangle
+    )
+}
+
+/// Gradient magnitude of every pixel, from 0 to 255
+fn edges(image: &GrayImage) -> GrayImage {
+    let (width, height) = image.dimensions();
+    let at = |x: i64, y: i64| {
+        let x = x.clamp(0, width as i64 - 1) as u32;
+        let y = y.clamp(0, height as i64 - 1) as u32;
+        image.get_pixel(x, y).0[0] as f32
+    };
+
+    GrayImage::from_fn(width, height, |x, y| {
+        let (x, y) = (x as i64, y as i64);
+        let gx = at(x + 1, y - 1) + 2.0 * at(x + 1, y) + at(x + 1, y + 1)
+            - at(x - 1, y - 1) - 2.0 * at(x - 1, y) - at(x - 1, y + 1);
+        let gy = at(x - 1, y + 1) + 2.0 * at(x, y + 1) + at(x + 1, y + 1)
+            - at(x - 1, y - 1) - 2.0 * at(x, y - 1) - at(x + 1, y - 1);
+
+        Luma([((gx * gx + gy * gy).sqrt() / 4.0).min(255.0) as u8])
+    })
+}
+
+/// One level of the search, the edges of both pages at the same size
+struct Level {
+    /// Strongest edge points of the reference, relative to its size
+    points: Vec<(f32, f32)>,
+
+    /// Edges of the page, blurred so the score changes smoothly
+    page: GrayImage,
+
+    aspect: f32,
+}
+
+impl Level {
+    fn new(reference: &GrayImage, page: &GrayImage, side: u32) -> Self {
+        let (width, height) = reference.dimensions();
+        let ratio = side as f32 / width.max(height) as f32;
+        let (width, height) = (
+            ((width as f32 * ratio) as u32).max(1),
+            ((height as f32 * ratio) as u32).max(1)
+        );
+        let reference = edges(&imageops::resize(
+            reference, width, height, FilterType::Triangle));
+        let page = imageops::blur(&edges(&imageops::resize(
+            page, width, height, FilterType::Triangle)), 1.0);
+
+        // Keep the strongest edges, evenly spread if there are too many
+        let mut strong: Vec<(u32, u32, u8)> = reference.enumerate_pixels()
+            .map(|(x, y, pixel)| (x, y, pixel.0[0]))
+            .filter(|(_, _, edge)| *edge >= 32)
+            .collect();
+        strong.sort_by_key(|(_, _, edge)| std::cmp::Reverse(*edge));
+        strong.truncate(MAX_POINTS * 4);
+        let step = (strong.len() / MAX_POINTS).max(1);
+        let points = strong.iter()
+            .step_by(step)
+            .map(|(x, y, _)| (
+                (*x as f32 + 0.5) / width as f32,
+                (*y as f32 + 0.5) / height as f32
+            ))
+            .collect();
+
+        Self {
+            points,
+            page,
+            aspect: width as f32 / height as f32
+        }
+    }
+
+    /// Mean edge strength of the page under the edges of the reference
+    /// moved with `alignment`, from 0 to 1
+    fn score(&self, alignment: &Alignment) -> f32 {
+        if self.points.is_empty() {
+            return 0.0;
+        }
+
+        let (width, height) = self.page.dimensions();
+        let sum: u32 = self.points.iter()
+            .map(|point| {
+                let (x, y) = alignment.apply(*point, self.aspect);
+                let (x, y) = (x * width as f32, y * height as f32);
+                if x < 0.0 || y < 0.0 || x >= width as f32 || y >= height as f32 {
+                    0
+                } else {
+                    self.page.get_pixel(x as u32, y as u32).0[0] as u32
+                }
+            })
+            .sum();
+
+        sum as f32 / (255.0 * self.points.len() as f32)
+    }
+
+    /// Best alignment around `center`, moving up to the given number of
+    /// steps of (shift, scale, angle) each
+    fn search(
+        &self,
+        center: Alignment,
+        (shifts, scales, angles): (i32, i32, i32),
+        (shift, scale, angle): (f32, f32, f32)
+    ) -> Alignment {
+        let mut best = Alignment {
+            score: self.score(&center),
+            ..center
+        };
+        for da in -angles..=angles {
+            for ds in -scales..=scales {
+                for dy in -shifts..=shifts {
+                    for dx in -shifts..=shifts {
+                        let mut candidate = Alignment {
+                            dx: center.dx + dx as f32 * shift,
+                            dy: center.dy + dy as f32 * shift,
+                            scale: center.scale + ds as f32 * scale,
+                            angle: center.angle + da as f32 * angle,
+                            score: 0.0
+                        };
+                        candidate.score = self.score(&candidate);
+                        if candidate.score > best.score {
+                            best = candidate;
+                        }
+                    }
+                }
+            }
+        }
+
+        best
+    }
+}
+
+/// Find the shift, scale and small rotation that best place the edges of
+/// `reference` over the edges of `page`, searching coarse to fine
+///
+/// The page is left as it is if the best alignment matches poorly or isn't
+/// clearly better.
+pub(crate) fn register(reference: &GrayImage, page: &Image) -> Alignment {
+    let page = imageops::grayscale(page.buffer());
+
+    // Coarse search over the whole range, shifting one pixel at a time
+    let coarse = Level::new(reference, &page, COARSE_SIZE);
+    let shift = 1.0 / COARSE_SIZE as f32;
+    let mut best = Alignment::default();
+    for angle in [0.0, -MAX_ANGLE / 2.0, MAX_ANGLE / 2.0, -MAX_ANGLE, MAX_ANGLE] {
+        for step in -4..=4 {
+            let scale = 1.0 + step as f32 * MAX_SCALE / 4.0;
+            let candidate = coarse.search(
+                Alignment { scale, angle, ..Alignment::default() },
+                ((MAX_SHIFT / shift) as i32, 0, 0),
+                (shift, 0.0, 0.0)
+            );
+            if candidate.score > best.score {
+                best = candidate;
+            }
+        }
+    }
+
+    // Refine around the best one on finer levels with smaller steps
+    let mut steps = (shift, MAX_SCALE / 8.0, MAX_ANGLE / 4.0);
+    let mut side = COARSE_SIZE;
+    let mut level = coarse;
+    while side < REFERENCE_SIZE {
+        side *= 2;
+        steps = (steps.0 / 2.0, steps.1 / 2.0, steps.2 / 2.0);
+        level = Level::new(reference, &page, side);
+        best = level.search(best, (1, 1, 1), steps);
+        best = level.search(best, (1, 1, 1), (steps.0 / 2.0, steps.1 / 2.0, steps.2 / 2.0));
+    }
+
+    let identity = Alignment {
+        score: level.score(&Alignment::default()),
+        ..Alignment::default()
+    };
+    if best.score < MIN_SCORE || best.score <= identity.score * (1.0 + MIN_GAIN) {
+        return identity;
+    }
+
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgba, RgbaImage};
+
+    use super::*;
+
+    const WIDTH: u32 = 400;
+    const HEIGHT: u32 = 300;
+
+    /// If the point of the reference page is ink, a frame around the page
+    /// with a filled box and an empty one inside
+    fn ink(x: f32, y: f32) -> bool {
+        let inside = |(x1, y1, x2, y2): (f32, f32, f32, f32)| {
+            x >= x1 && x < x2 && y >= y1 && y < y2
+        };
+        let outline = |(x1, y1, x2, y2): (f32, f32, f32, f32)| {
+            inside((x1, y1, x2, y2))
+                && !inside((x1 + 4.0, y1 + 4.0, x2 - 4.0, y2 - 4.0))
+        };
+
+        outline((20.0, 20.0, 380.0, 280.0))
+            || inside((60.0, 60.0, 160.0, 140.0))
+            || outline((220.0, 160.0, 340.0, 250.0))
+    }
+
+    /// The reference page scaled by `scale` around its center and shifted by
+    /// (dx, dy) pixels
+    fn page(scale: f32, (dx, dy): (f32, f32)) -> Image {
+        let (cx, cy) = (WIDTH as f32 / 2.0, HEIGHT as f32 / 2.0);
+        let buffer = RgbaImage::from_fn(WIDTH, HEIGHT, |x, y| {
+            let x = (x as f32 + 0.5 - dx - cx) / scale + cx;
+            let y = (y as f32 + 0.5 - dy - cy) / scale + cy;
+            if ink(x, y) {
+                Rgba([0, 0, 0, 255])
+            } else {
+                Rgba([255, 255, 255, 255])
+            }
+        });
+
+        Image::from_buffer(buffer, "page.png")
+    }
+
+    #[test]
+    fn register_finds_the_shift_and_scale() {
+        let reference = reference(&page(1.0, (0.0, 0.0)), REFERENCE_SIZE);
+        let (scale, (dx, dy)) = (1.04, (12.0, -7.0));
+        let alignment = register(&reference, &page(scale, (dx, dy)));
+
+        let (width, height) = (WIDTH as f32, HEIGHT as f32);
+        for (x, y) in [(20.0, 20.0), (380.0, 280.0), (220.0, 160.0)] {
+            let (ax, ay) = alignment.apply((x / width, y / height), width / height);
+            let expected = (
+                (x - width / 2.0) * scale + width / 2.0 + dx,
+                (y - height / 2.0) * scale + height / 2.0 + dy
+            );
+            assert!((ax * width - expected.0).abs() <= 1.0
+                && (ay * height - expected.1).abs() <= 1.0,
+                "({}, {}) placed at ({}, {}) instead of {:?}, {:?}",
+                x, y, ax * width, ay * height, expected, alignment);
+        }
+    }
+
+    #[test]
+    fn register_leaves_a_blank_page_as_it_is() {
+        let reference = reference(&page(1.0, (0.0, 0.0)), REFERENCE_SIZE);
+        let blank = Image::from_buffer(
+            RgbaImage::from_pixel(WIDTH, HEIGHT, Rgba([255, 255, 255, 255])),
+            "blank.png"
+        );
+
+        let alignment = register(&reference, &blank);
+        assert_eq!((alignment.dx, alignment.dy), (0.0, 0.0));
+        assert_eq!((alignment.scale, alignment.angle), (1.0, 0.0));
+    }
+}
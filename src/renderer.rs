@@ -1,33 +1,104 @@
 use pixels::wgpu;
 use tiny_skia::*;
+use winit::event::{ModifiersState, VirtualKeyCode};
 
 use crate::color_picker::ColorPicker;
-use crate::regions::Regions;
+use crate::panel::{Settings, Tool};
+use crate::regions::{Region, Regions, DUPLICATE_OFFSET};
 use crate::crosshair::Crosshair;
+use crate::loupe::Loupe;
+use crate::snap::{ImageLines, Snap};
 use crate::ImageCropper;
 
+/// Distance from a region, in pixels of the window, a click selects it at
+const HIT_MARGIN: f32 = 4.0;
+
+/// Drag of the mouse started with the select tool
+enum Drag {
+    /// Moving the selected regions, from the last point seen
+    Move {
+        last: (f32, f32)
+    },
+
+    /// Selecting the regions inside a rectangle, added to the selection
+    /// if `add`
+    Select {
+        start: (f32, f32),
+        add: bool
+    },
+}
+
 pub struct MasterRenderer {
     mouse_pos_x: f32,
     mouse_pos_y: f32,
     pub regions: Regions,
     pub color_picker: ColorPicker,
     pub crosshair: Crosshair,
+    pub loupe: Loupe,
+    pub settings: Settings,
+
+    /// Keyboard modifiers held, Ctrl disables snapping
+    pub modifiers: ModifiersState,
+
+    /// Lines of the image to snap to, and the cursor snapped
+    lines: ImageLines,
+    snap: Snap,
+
+    /// Regions copied to be pasted
+    clipboard: Vec<Region>,
+
+    /// Drag in progress, in pixels of the original image
+    drag: Option<Drag>,
+
+    /// Point of the last click that selected a region, clicking again on it
+    /// selects the next region below
+    last_click: Option<(f32, f32)>,
 }
 
 impl MasterRenderer {
     /// Called when the window is created to create this handler
-    pub fn create<'a>(app: &'a mut ImageCropper) -> Self {
+    pub fn create(app: &mut ImageCropper) -> Self {
         println!("Window created");
+        let mut regions = app.regions.take().unwrap_or_default();
+        regions.set_image_size(app.image.dimensions());
 
         Self {
             mouse_pos_x: 0.0,
             mouse_pos_y: 0.0,
-            regions: Regions::new(),
-            color_picker: ColorPicker::new(&app.event_loop.as_ref().unwrap()),
+            regions,
+            color_picker: ColorPicker::new(
+                app.event_loop.as_ref().unwrap(),
+                std::mem::take(&mut app.palettes),
+                std::mem::take(&mut app.strip)
+            ),
             crosshair: Crosshair::new(app.width as f32, app.height as f32),
+            loupe: Loupe::new(8, 10.0),
+            settings: Settings {
+                preprocess: app.preprocess,
+                preprocess_draft: app.preprocess,
+                ..Settings::default()
+            },
+            modifiers: ModifiersState::empty(),
+            lines: ImageLines::detect(&app.image),
+            snap: Snap::default(),
+            clipboard: Vec::new(),
+            drag: None,
+            last_click: None,
         }
     }
 
+    /// The image was replaced by a new version or by another page
+    pub fn image_changed(&mut self, app: &mut ImageCropper) {
+        self.lines = ImageLines::detect(&app.image);
+        self.regions.set_image_size(app.image.dimensions());
+        self.drag = None;
+        self.last_click = None;
+        self.update_snap(app);
+        self.color_picker.image_changed();
+
+        self.request_redraw(app);
+    }
+
     /// The mouse moved
     pub fn mouse_move(
         &mut self, 
@@ -36,6 +107,44 @@ impl MasterRenderer {
     ) {
         self.mouse_pos_x = pos_x;
         self.mouse_pos_y = pos_y;
+        self.update_snap(app);
+
+        if let Some(Drag::Move { last }) = &mut self.drag {
+            // The regions stop at the edges of the image, the cursor doesn't
+            let (x, y) = self.snap.point;
+            let (dx, dy) = self.regions.move_selected(x - last.0, y - last.1);
+            *last = (last.0 + dx, last.1 + dy);
+        }
+
+        self.request_redraw(app);
+    }
+
+    /// Snap the cursor while a drawing tool is used
+    fn update_snap(&mut self, app: &ImageCropper) {
+        let point = (self.mouse_pos_x / app.ratio, self.mouse_pos_y / app.ratio);
+        let snapping = &self.settings.snapping;
+        let drawing = matches!(self.settings.tool,
+            Tool::Rectangle | Tool::Polygon);
+        self.snap = if drawing && snapping.enabled && !self.modifiers.ctrl() {
+            self.lines.snap(
+                point,
+                &self.regions,
+                snapping,
+                snapping.distance / app.ratio
+            )
+        } else {
+            Snap::free(point)
+        };
+    }
+
+    /// The keyboard modifiers changed
+    pub fn modifiers_changed(
+        &mut self,
+        app: &mut ImageCropper,
+        modifiers: ModifiersState
+    ) {
+        self.modifiers = modifiers;
+        self.update_snap(app);
 
         self.request_redraw(app);
     }
@@ -44,55 +153,265 @@ impl MasterRenderer {
         &mut self,
         app: &mut ImageCropper 
     ) {
-        if self.color_picker.show {
-            self.regions.deselect();
-            self.color_picker.show = false;
+        if self.color_picker.prompt_incomplete {
+            return;
+        }
+
+        let (x, y) = self.snap.point;
+        match self.settings.tool {
+            Tool::Rectangle => {
+                if self.regions.is_finished() {
+                    self.regions.start(x, y);
+                } else {
+                    self.regions.finish(x, y);
+                }
+            }
+            Tool::Polygon => {
+                self.regions.add_polygon_point(x, y, 8.0 / app.ratio);
+            }
+            Tool::Select => self.select_press(app, (x, y)),
+            Tool::Eyedropper => {
+                let colors = &mut self.color_picker.colors;
+                if let Some(pixel) = app.image.average_color(
+                    x as u32,
+                    y as u32,
+                    colors.patch_size
+                ) {
+                    let [r, g, b, _] = pixel.0;
+                    colors.apply_sample(
+                        &mut self.regions,
+                        egui::Color32::from_rgb(r, g, b)
+                    );
+                }
+            }
+        }
+
+        self.request_redraw(app);
+    }
+
+    /// Left press with the select tool, on a region selects it and starts
+    /// moving the selection, on the background starts a rubber band
+    /// selection, with Shift the selection is extended
+    fn select_press(&mut self, app: &ImageCropper, (x, y): (f32, f32)) {
+        let shift = self.modifiers.shift();
+        let margin = HIT_MARGIN / app.ratio;
+        let hit = if shift {
+            // Toggle the topmost region, whether selected or not
+            self.last_click = None;
+            self.regions.regions_at(x, y, margin).first().copied()
         } else {
-            if self.regions.is_finished() {
-                self.regions.start(
-                    self.mouse_pos_x,
-                    self.mouse_pos_y
-                );
-            } else {
-                self.regions.finish(
-                    self.mouse_pos_x,
-                    self.mouse_pos_y
-                );
+            let cycle = self.repeated_click((x, y), margin);
+            self.regions.region_at(x, y, margin, cycle)
+        };
+        match hit {
+            Some(idx) if shift => self.regions.toggle_selection(idx),
+            Some(idx) => {
+                if !self.regions.is_selected(idx) {
+                    self.regions.select(idx);
+                }
+                self.drag = Some(Drag::Move { last: (x, y) });
+            }
+            None => {
+                if !shift {
+                    self.regions.deselect();
+                }
+                self.drag = Some(Drag::Select {
+                    start: (x, y),
+                    add: shift
+                });
             }
         }
+    }
+
+    /// The left button was released, ending any drag
+    pub fn mouse_left_release(&mut self, app: &mut ImageCropper) {
+        if let Some(Drag::Select { start, add }) = self.drag.take() {
+            self.regions.select_in_rect(start, self.snap.point, add);
+        }
 
         self.request_redraw(app);
     }
 
+    /// Whether a click at `point` is on the same spot as the last one, and
+    /// remember it for the next one
+    fn repeated_click(&mut self, point: (f32, f32), margin: f32) -> bool {
+        let repeated = self.last_click.is_some_and(|last| {
+            (last.0 - point.0).abs() <= margin
+                && (last.1 - point.1).abs() <= margin
+        });
+        self.last_click = Some(point);
+
+        repeated
+    }
+
+    /// Select the region under the cursor whatever the tool is, clicking
+    /// again on the same spot selects the next overlapping region
     pub fn mouse_right_click(
         &mut self,
         app: &mut ImageCropper 
     ) {
-        if self.regions.select_collided_region(
-            self.mouse_pos_x,
-            self.mouse_pos_y
-        ) {
-            self.color_picker.show = true;
+        let (x, y) = (self.mouse_pos_x / app.ratio, self.mouse_pos_y / app.ratio);
+        let margin = HIT_MARGIN / app.ratio;
+        let cycle = self.repeated_click((x, y), margin);
+        if self.regions.select_at(x, y, margin, cycle) {
             self.request_redraw(app);
         }
     }
 
+    /// A key was pressed over the image
+    pub fn key_pressed(
+        &mut self,
+        app: &mut ImageCropper,
+        key: VirtualKeyCode
+    ) {
+        let ctrl = self.modifiers.ctrl();
+        match key {
+            VirtualKeyCode::A if ctrl => self.regions.select_all(),
+            VirtualKeyCode::C if ctrl => {
+                self.clipboard = self.regions.selected_regions();
+            }
+            VirtualKeyCode::V if ctrl => {
+                // Paste with the top left corner of the copied regions at the
+                // cursor
+                let (x, y) = self.snap.point;
+                let corner = self.clipboard.iter()
+                    .filter_map(Region::bounds)
+                    .map(|(x1, y1, _, _)| (x1, y1))
+                    .reduce(|(ax, ay), (bx, by)| (ax.min(bx), ay.min(by)));
+                if let Some((x1, y1)) = corner {
+                    self.regions.paste(&self.clipboard, x - x1, y - y1);
+                }
+            }
+            VirtualKeyCode::D if ctrl => {
+                let selected = self.regions.selected_regions();
+                self.regions.paste(&selected, DUPLICATE_OFFSET, DUPLICATE_OFFSET);
+            }
+            VirtualKeyCode::Left
+                    | VirtualKeyCode::Right
+                    | VirtualKeyCode::Up
+                    | VirtualKeyCode::Down => {
+                // Nudge the selection one pixel of the original image, or ten
+                // with Shift
+                let step = if self.modifiers.shift() { 10.0 } else { 1.0 };
+                let (dx, dy) = match key {
+                    VirtualKeyCode::Left => (-step, 0.0),
+                    VirtualKeyCode::Right => (step, 0.0),
+                    VirtualKeyCode::Up => (0.0, -step),
+                    _ => (0.0, step),
+                };
+                self.regions.move_selected(dx, dy);
+            }
+            VirtualKeyCode::Escape => {
+                if self.regions.is_finished() {
+                    self.regions.deselect();
+                } else {
+                    self.regions.cancel();
+                }
+            }
+            VirtualKeyCode::Return => self.regions.close_polygon(),
+            VirtualKeyCode::H => {
+                let overlay = &mut self.settings.overlay;
+                overlay.visible = !overlay.visible;
+            }
+            VirtualKeyCode::L => {
+                let overlay = &mut self.settings.overlay;
+                overlay.loupe = !overlay.loupe;
+            }
+            VirtualKeyCode::Delete => self.regions.remove_selected(),
+            VirtualKeyCode::PageUp => {
+                let page = app.current_page();
+                if page > 0 {
+                    app.show_page(self, page - 1);
+                }
+                return;
+            }
+            VirtualKeyCode::PageDown => {
+                let page = app.current_page();
+                app.show_page(self, page + 1);
+                return;
+            }
+            _ => return
+        }
+
+        self.request_redraw(app);
+    }
+
     pub fn buff_render(
         &mut self,
         app: &mut ImageCropper
     ) {
-        let mut pixmap = PixmapMut::from_bytes(
+        let mut pixmap = match PixmapMut::from_bytes(
             app.pixbuf.get_frame_mut(),
             app.width,
             app.height
-        ).unwrap();
+        ) {
+            Some(pixmap) => pixmap,
+            None => return
+        };
 
-        self.regions.render(&mut pixmap);
+        // The regions and the crosshair follow the snapped cursor
+        let (x, y) = self.snap.point;
+        let cursor = (x * app.ratio, y * app.ratio);
+        self.regions.render(
+            &mut pixmap,
+            app.ratio,
+            cursor,
+            &self.settings.overlay
+        );
+        self.snap.render(&mut pixmap, app.ratio);
+        if let Some(Drag::Select { start, .. }) = self.drag {
+            Self::render_selection_band(
+                &mut pixmap,
+                (start.0 * app.ratio, start.1 * app.ratio),
+                cursor
+            );
+        }
 
         self.crosshair.render(
             &mut pixmap,
-            self.mouse_pos_x,
-            self.mouse_pos_y
+            cursor.0,
+            cursor.1
+        );
+
+        if self.settings.overlay.loupe {
+            self.loupe.render(
+                &mut pixmap,
+                &app.image,
+                app.ratio,
+                (self.mouse_pos_x, self.mouse_pos_y)
+            );
+        }
+    }
+
+    /// Draw the rectangle of a rubber band selection
+    fn render_selection_band(
+        pixmap: &mut PixmapMut,
+        (ax, ay): (f32, f32),
+        (bx, by): (f32, f32)
+    ) {
+        let rect = match Rect::from_ltrb(
+            ax.min(bx), ay.min(by),
+            ax.max(bx), ay.max(by)
+        ) {
+            Some(rect) => rect,
+            None => return
+        };
+
+        let mut paint = Paint::default();
+        paint.set_color_rgba8(30, 110, 220, 40);
+        pixmap.fill_rect(rect, &paint, Transform::identity(), None);
+        paint.set_color_rgba8(30, 110, 220, 255);
+        let stroke = Stroke {
+            width: 1.0,
+            dash: StrokeDash::new(vec![4.0, 3.0], 0.0),
+            ..Stroke::default()
+        };
+        pixmap.stroke_path(
+            &PathBuilder::from_rect(rect),
+            &paint,
+            &stroke,
+            Transform::identity(),
+            None
         );
     }
 
@@ -112,8 +431,22 @@ impl MasterRenderer {
     }
 
     pub fn request_redraw(&mut self, app: &mut ImageCropper) {
-        self.regions.update_selected_color(self.color_picker.color);
-        self.color_picker.prepare(&app.window);
+        let tool = self.settings.tool;
+        let preprocess = self.settings.preprocess;
+        self.color_picker.prepare(
+            &app.window,
+            &app.image,
+            &mut self.regions,
+            &mut self.settings
+        );
+
+        // Switching tools drops the region being drawn with the previous one
+        if self.settings.tool != tool {
+            self.regions.cancel();
+        }
+        if self.settings.preprocess != preprocess {
+            app.set_preprocess(self, self.settings.preprocess);
+        }
         app.window.request_redraw();
     }
 }
@@ -0,0 +1,189 @@
+use image::Rgba;
+use tiny_skia::*;
+
+use crate::image::Image;
+use crate::regions::Regions;
+
+/// Luma below which a pixel is considered dark when detecting lines
+const LINE_LUMA: u32 = 128;
+
+/// Minimum length of a line, relative to the side of the image it runs
+/// along, to be snapped to
+const MIN_LINE_LENGTH: f32 = 0.25;
+
+/// What the cursor snaps to while drawing regions
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Snapping {
+    pub enabled: bool,
+
+    /// Edges and centers of the other regions
+    pub regions: bool,
+
+    /// Borders of the image
+    pub border: bool,
+
+    /// Long horizontal and vertical lines of the image, like plot frames
+    pub lines: bool,
+
+    /// Maximum distance to snap, in pixels of the window
+    pub distance: f32,
+}
+
+impl Default for Snapping {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            regions: true,
+            border: true,
+            lines: true,
+            distance: 8.0
+        }
+    }
+}
+
+/// A snapped point, in pixels of the original image, with the coordinates
+/// it was snapped to on each axis
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Snap {
+    pub point: (f32, f32),
+    pub guides: (Option<f32>, Option<f32>),
+}
+
+impl Snap {
+    /// A point that isn't snapped to anything
+    pub fn free(point: (f32, f32)) -> Self {
+        Self {
+            point,
+            guides: (None, None)
+        }
+    }
+
+    /// Draw a guide line across the window for each snapped axis
+    pub fn render(&self, pixmap: &mut PixmapMut, ratio: f32) {
+        let (width, height) = (pixmap.width() as f32, pixmap.height() as f32);
+        let mut pb = PathBuilder::new();
+        if let Some(x) = self.guides.0 {
+            pb.move_to(x * ratio, 0.0);
+            pb.line_to(x * ratio, height);
+        }
+        if let Some(y) = self.guides.1 {
+            pb.move_to(0.0, y * ratio);
+            pb.line_to(width, y * ratio);
+        }
+        let path = match pb.finish() {
+            Some(path) => path,
+            None => return
+        };
+
+        let mut paint = Paint::default();
+        paint.set_color_rgba8(230, 0, 180, 255);
+        let stroke = Stroke {
+            width: 1.0,
+            dash: StrokeDash::new(vec![4.0, 4.0], 0.0),
+            ..Stroke::default()
+        };
+        pixmap.stroke_path(&path, &paint, &stroke, Transform::identity(), None);
+    }
+}
+
+/// Long horizontal and vertical lines of an image, the candidates to snap to
+/// besides the regions
+pub(crate) struct ImageLines {
+    /// Columns of the vertical lines
+    columns: Vec<f32>,
+
+    /// Rows of the horizontal lines
+    rows: Vec<f32>,
+
+    /// Size of the image
+    size: (f32, f32),
+}
+
+impl ImageLines {
+    /// Find the rows and columns with a run of dark pixels longer than a
+    /// quarter of the side of the image
+    pub fn detect(image: &Image) -> Self {
+        let (width, height) = image.dimensions();
+        let buffer = image.buffer();
+        let dark = |x: u32, y: u32| {
+            let Rgba([r, g, b, _]) = *buffer.get_pixel(x, y);
+            (299 * r as u32 + 587 * g as u32 + 114 * b as u32) / 1000
+                < LINE_LUMA
+        };
+
+        // Longest run of dark pixels of every row and column
+        let mut row_runs = vec![0; height as usize];
+        let mut column_runs = vec![0; width as usize];
+        let mut column_current = vec![0; width as usize];
+        for y in 0..height {
+            let mut current = 0;
+            for x in 0..width {
+                let xi = x as usize;
+                if dark(x, y) {
+                    current += 1;
+                    column_current[xi] += 1;
+                } else {
+                    current = 0;
+                    column_current[xi] = 0;
+                }
+                row_runs[y as usize] = row_runs[y as usize].max(current);
+                column_runs[xi] = column_runs[xi].max(column_current[xi]);
+            }
+        }
+
+        let long = |runs: Vec<u32>, side: u32| runs.into_iter()
+            .enumerate()
+            .filter(|(_, run)| *run as f32 >= side as f32 * MIN_LINE_LENGTH)
+            .map(|(idx, _)| idx as f32)
+            .collect();
+
+        Self {
+            columns: long(column_runs, height),
+            rows: long(row_runs, width),
+            size: (width as f32, height as f32)
+        }
+    }
+
+    /// Snap `point`, in pixels of the original image, to the nearest
+    /// candidate of each axis closer than `tolerance`
+    pub fn snap(
+        &self,
+        point: (f32, f32),
+        regions: &Regions,
+        snapping: &Snapping,
+        tolerance: f32
+    ) -> Snap {
+        let mut xs = Vec::new();
+        let mut ys = Vec::new();
+        if snapping.border {
+            xs.extend([0.0, self.size.0]);
+            ys.extend([0.0, self.size.1]);
+        }
+        if snapping.lines {
+            xs.extend(&self.columns);
+            ys.extend(&self.rows);
+        }
+        if snapping.regions {
+            for (x1, y1, x2, y2) in regions.iter().filter_map(|r| r.bounds()) {
+                xs.extend([x1, (x1 + x2) / 2.0, x2]);
+                ys.extend([y1, (y1 + y2) / 2.0, y2]);
+            }
+        }
+
+        let nearest = |value: f32, candidates: &[f32]| candidates.iter()
+            .copied()
+            .map(|candidate| (candidate, (candidate - value).abs()))
+            .filter(|(_, distance)| *distance <= tolerance)
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(candidate, _)| candidate);
+        let guides = (nearest(point.0, &xs), nearest(point.1, &ys));
+
+        Snap {
+            point: (
+                guides.0.unwrap_or(point.0),
+                guides.1.unwrap_or(point.1)
+            ),
+            guides
+        }
+    }
+}
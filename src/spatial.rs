@@ -0,0 +1,89 @@
+use std::collections::HashMap;
+
+/// Side of the cells of the index, in pixels of the original image
+const CELL_SIZE: f32 = 256.0;
+
+/// Uniform grid over the image where each cell lists the regions whose
+/// bounds overlap it, so finding the regions around a point only looks at
+/// the regions nearby
+#[derive(Debug, Default)]
+pub(crate) struct GridIndex {
+    cells: HashMap<(i32, i32), Vec<usize>>,
+}
+
+/// Cell that contains the coordinate
+fn cell(value: f32) -> i32 {
+    (value / CELL_SIZE).floor() as i32
+}
+
+impl GridIndex {
+    /// Index the bounds (x1, y1, x2, y2) of every region, by index
+    pub fn build(
+        bounds: impl Iterator<Item = (usize, (f32, f32, f32, f32))>
+    ) -> Self {
+        let mut cells: HashMap<(i32, i32), Vec<usize>> = HashMap::new();
+        for (idx, (x1, y1, x2, y2)) in bounds {
+            for row in cell(y1)..=cell(y2) {
+                for column in cell(x1)..=cell(x2) {
+                    cells.entry((column, row)).or_default().push(idx);
+                }
+            }
+        }
+
+        Self { cells }
+    }
+
+    /// Indices of the regions whose bounds may overlap the square of side
+    /// `2 * margin` centered at (px, py), sorted and without repetitions
+    pub fn query(&self, px: f32, py: f32, margin: f32) -> Vec<usize> {
+        let mut found = Vec::new();
+        for row in cell(py - margin)..=cell(py + margin) {
+            for column in cell(px - margin)..=cell(px + margin) {
+                if let Some(indices) = self.cells.get(&(column, row)) {
+                    found.extend(indices);
+                }
+            }
+        }
+        found.sort_unstable();
+        found.dedup();
+
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index() -> GridIndex {
+        GridIndex::build([
+            (0, (10.0, 10.0, 100.0, 100.0)),
+            (1, (200.0, 200.0, 600.0, 300.0)),
+            (2, (-300.0, -50.0, -260.0, -10.0)),
+            (3, (2050.0, 2050.0, 2100.0, 2100.0)),
+        ].into_iter())
+    }
+
+    #[test]
+    fn query_finds_the_regions_nearby() {
+        let index = index();
+        assert_eq!(index.query(50.0, 50.0, 0.0), vec![0, 1]);
+        assert_eq!(index.query(550.0, 250.0, 0.0), vec![1]);
+        assert_eq!(index.query(-280.0, -30.0, 0.0), vec![2]);
+        assert_eq!(index.query(1000.0, 1000.0, 0.0), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn query_looks_at_the_cells_within_the_margin() {
+        let index = index();
+        assert_eq!(index.query(2040.0, 2040.0, 0.0), Vec::<usize>::new());
+        assert_eq!(index.query(2040.0, 2040.0, 20.0), vec![3]);
+    }
+
+    #[test]
+    fn query_lists_each_region_once() {
+        // Region 1 spans several cells, all of them around the point
+        let found = index().query(400.0, 256.0, 300.0);
+        assert_eq!(found, vec![0, 1]);
+    }
+}
@@ -0,0 +1,424 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use image::GrayImage;
+
+use crate::error::{Error, Result};
+use crate::image::Image;
+use crate::palette::{parse_hex, to_hex};
+use crate::regions::{Region, RegionState};
+use crate::register::{self, Alignment, REFERENCE_SIZE};
+
+/// Regions saved to be drawn again on other documents with the same layout,
+/// their coordinates are relative to the size of the image, from 0 to 1, so
+/// they apply to any density
+#[derive(Debug, Clone, Default)]
+pub struct Template {
+    pub name: String,
+    regions: Vec<Region>,
+
+    /// Downscaled page the regions were drawn on, to align other pages to it
+    reference: Option<GrayImage>,
+}
+
+impl Template {
+    /// Template of the complete `regions` drawn over an image of `size`, cut
+    /// to the image, the ones outside it are left out
+    pub fn new<'a>(
+        name: impl Into<String>,
+        regions: impl IntoIterator<Item = &'a Region>,
+        (width, height): (u32, u32)
+    ) -> Self {
+        let regions = regions.into_iter()
+            .filter(|region| region.state.is_complete())
+            .map(|region| {
+                let mut region = region.clone();
+                region.scale(1.0 / width as f32, 1.0 / height as f32);
+                region.visible = true;
+                match &mut region.state {
+                    RegionState::Complete { x1, y1, x2, y2 } => {
+                        for coordinate in [x1, y1, x2, y2] {
+                            *coordinate = coordinate.clamp(0.0, 1.0);
+                        }
+                    }
+                    RegionState::Polygon { points } => {
+                        for (x, y) in points {
+                            (*x, *y) = (x.clamp(0.0, 1.0), y.clamp(0.0, 1.0));
+                        }
+                    }
+                    _ => {}
+                }
+                region
+            })
+            .filter(|region| matches!(region.size(),
+                Some((width, height)) if width > 0.0 && height > 0.0))
+            .collect();
+
+        Self {
+            name: name.into(),
+            regions,
+            reference: None
+        }
+    }
+
+    /// Keep a downscaled copy of the page the regions were drawn on, so
+    /// other pages can be aligned to it
+    pub fn with_reference(mut self, image: &Image) -> Self {
+        self.reference = Some(register::reference(image, REFERENCE_SIZE));
+        self
+    }
+
+    pub fn has_reference(&self) -> bool {
+        self.reference.is_some()
+    }
+
+    /// Find how `page` is shifted, scaled and rotated from the reference
+    /// page and move the regions the same way, rectangles stay upright
+    /// covering their rotated corners
+    ///
+    /// Returns the alignment found, the identity if none matches clearly,
+    /// `None` if there is no reference page.
+    pub fn align(&mut self, page: &Image) -> Option<Alignment> {
+        let reference = self.reference.as_ref()?;
+        let alignment = register::register(reference, page);
+        let aspect = reference.width() as f32 / reference.height() as f32;
+
+        let transform = |point| alignment.apply(point, aspect);
+        for region in &mut self.regions {
+            match &mut region.state {
+                RegionState::Complete { x1, y1, x2, y2 } => {
+                    let corners = [(*x1, *y1), (*x2, *y1), (*x1, *y2), (*x2, *y2)]
+                        .map(transform);
+                    *x1 = corners.iter().map(|c| c.0).fold(f32::MAX, f32::min);
+                    *y1 = corners.iter().map(|c| c.1).fold(f32::MAX, f32::min);
+                    *x2 = corners.iter().map(|c| c.0).fold(f32::MIN, f32::max);
+                    *y2 = corners.iter().map(|c| c.1).fold(f32::MIN, f32::max);
+                }
+                RegionState::Polygon { points } => {
+                    for point in points {
+                        *point = transform(*point);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Some(alignment)
+    }
+
+    /// The regions placed over an image of `size`
+    pub fn regions(&self, (width, height): (u32, u32)) -> Vec<Region> {
+        self.regions.iter()
+            .map(|region| {
+                let mut region = region.clone();
+                region.scale(width as f32, height as f32);
+                region
+            })
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.regions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.regions.is_empty()
+    }
+
+    /// Directory of the templates saved by name,
+    /// `$XDG_CONFIG_HOME/plotview/templates` or `~/.config/plotview/templates`
+    pub fn default_dir() -> Option<PathBuf> {
+        let config = std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME")
+                .map(|home| Path::new(&home).join(".config")))?;
+
+        Some(config.join("plotview").join("templates"))
+    }
+
+    /// File of the template named `name`, `name` itself if it contains a
+    /// `/` or else `name` on the default directory
+    pub fn path(name: &str) -> Option<PathBuf> {
+        if name.contains('/') {
+            return Some(PathBuf::from(name));
+        }
+
+        Self::default_dir().map(|dir| dir.join(name))
+    }
+
+    /// File of the template named `name`, see [`Template::path`], `None` if
+    /// it doesn't exist
+    pub fn find(name: &str) -> Option<PathBuf> {
+        Self::path(name).filter(|path| path.is_file())
+    }
+
+    /// Load a template file, each region starts with its name between
+    /// brackets followed by its properties, lines starting with `#` are
+    /// comments
+    ///
+    /// ```text
+    /// [Figure 1]
+    /// color = #e69f00
+    /// key = #1f4e79
+    /// threshold = 200
+    /// rect = 0.1, 0.1, 0.5, 0.45
+    ///
+    /// [Figure 2]
+    /// polygon = 0.5 0.5, 0.9 0.5, 0.7 0.8
+    /// ```
+    ///
+    /// The template is named after the file, its reference page is the PNG
+    /// image on the same path with `.png` appended, if there is one.
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)?;
+        let regions = Self::parse(&text).map_err(|message| Error::Template {
+            path: path.to_owned(),
+            message
+        })?;
+        let name = path.file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        let reference_path = reference_path(path);
+        let reference = if reference_path.is_file() {
+            Some(image::open(&reference_path)?.into_luma8())
+        } else {
+            None
+        };
+
+        Ok(Self {
+            name,
+            regions,
+            reference
+        })
+    }
+
+    /// Save the template on `path`, and its reference page next to it as a
+    /// PNG image, creating their directory if needed
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        if let Some(reference) = &self.reference {
+            reference.save(reference_path(path))?;
+        }
+
+        let mut text = String::from(
+            "# Regions relative to the size of the image\n");
+        for region in &self.regions {
+            text.push_str(&format!("\n[{}]\n", region.name));
+            text.push_str(&format!("color = {}\n", to_hex(region.color)));
+            if let Some(key) = region.key_color {
+                text.push_str(&format!("key = {}\n", to_hex(key)));
+            }
+            text.push_str(&format!("threshold = {}\n", region.threshold));
+            match &region.state {
+                RegionState::Complete { x1, y1, x2, y2 } => {
+                    text.push_str(&format!("rect = {}, {}, {}, {}\n",
+                        x1, y1, x2, y2));
+                }
+                RegionState::Polygon { points } => {
+                    let points: Vec<String> = points.iter()
+                        .map(|(x, y)| format!("{} {}", x, y))
+                        .collect();
+                    text.push_str(&format!("polygon = {}\n",
+                        points.join(", ")));
+                }
+                _ => {}
+            }
+        }
+
+        fs::write(path, text)?;
+
+        Ok(())
+    }
+
+    fn parse(text: &str) -> std::result::Result<Vec<Region>, String> {
+        let mut regions: Vec<Region> = Vec::new();
+        for (idx, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |message: &str| format!("line {}: {}", idx + 1, message);
+
+            if let Some(name) = line.strip_prefix('[')
+                    .and_then(|line| line.strip_suffix(']')) {
+                // Without bounds till its shape is read
+                regions.push(Region::polygon(Vec::new(), name.trim()));
+                continue;
+            }
+
+            let (key, value) = line.split_once('=')
+                .ok_or_else(|| error("missing `=`"))?;
+            let region = regions.last_mut()
+                .ok_or_else(|| error("property outside of a region"))?;
+            let value = value.trim();
+            let numbers = || value.split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|number| !number.is_empty())
+                .map(|number| number.parse::<f32>()
+                    .map_err(|_| error(&format!("invalid number `{}`", number))))
+                .collect::<std::result::Result<Vec<_>, _>>();
+            match key.trim() {
+                "color" => {
+                    region.color = parse_hex(value)
+                        .ok_or_else(|| error("invalid color"))?;
+                }
+                "key" => {
+                    region.key_color = Some(parse_hex(value)
+                        .ok_or_else(|| error("invalid color"))?);
+                }
+                "threshold" => {
+                    region.threshold = value.parse()
+                        .map_err(|_| error("invalid threshold"))?;
+                }
+                "rect" => match numbers()?[..] {
+                    [x1, y1, x2, y2] => {
+                        if !(0.0..=x2).contains(&x1) || !(x1..=1.0).contains(&x2)
+                                || !(0.0..=y2).contains(&y1)
+                                || !(y1..=1.0).contains(&y2) {
+                            return Err(error("a rect goes from its top left \
+                                corner to its bottom right one, between 0 and 1"));
+                        }
+                        region.state = RegionState::Complete { x1, y1, x2, y2 };
+                    }
+                    _ => return Err(error("a rect takes 4 numbers")),
+                }
+                "polygon" => {
+                    let numbers = numbers()?;
+                    if numbers.len() < 6 || numbers.len() % 2 != 0 {
+                        return Err(error("a polygon takes at least 3 points"));
+                    }
+                    if !numbers.iter().all(|number| (0.0..=1.0).contains(number)) {
+                        return Err(error("the points of a polygon are between \
+                            0 and 1"));
+                    }
+                    region.state = RegionState::Polygon {
+                        points: numbers.chunks(2)
+                            .map(|point| (point[0], point[1]))
+                            .collect()
+                    };
+                }
+                key => return Err(error(&format!("unknown property `{}`", key))),
+            }
+        }
+
+        match regions.iter().find(|region| region.bounds().is_none()) {
+            Some(region) => Err(format!("region `{}` has no shape", region.name)),
+            None => Ok(regions)
+        }
+    }
+}
+
+/// PNG image with the reference page of the template on `path`, the
+/// extension is appended so names with dots keep their reference apart
+fn reference_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".png");
+
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use egui::Color32;
+    use image::{Rgba, RgbaImage};
+
+    use super::*;
+
+    #[test]
+    fn parse_reads_every_property() {
+        let regions = Template::parse("# A comment\n\
+            [Figure 1]\n\
+            color = #e69f00\n\
+            key = #1f4e79\n\
+            threshold = 200\n\
+            rect = 0.1, 0.1, 0.5, 0.45\n\
+            \n\
+            [Figure 2]\n\
+            polygon = 0.5 0.5, 0.9 0.5, 0.7 0.8\n").unwrap();
+
+        assert_eq!(regions.len(), 2);
+        assert_eq!(regions[0].name, "Figure 1");
+        assert_eq!(regions[0].color, Color32::from_rgb(0xe6, 0x9f, 0x00));
+        assert_eq!(regions[0].key_color, Some(Color32::from_rgb(0x1f, 0x4e, 0x79)));
+        assert_eq!(regions[0].threshold, 200);
+        assert_eq!(regions[0].state,
+            RegionState::Complete { x1: 0.1, y1: 0.1, x2: 0.5, y2: 0.45 });
+        assert_eq!(regions[1].state, RegionState::Polygon {
+            points: vec![(0.5, 0.5), (0.9, 0.5), (0.7, 0.8)]
+        });
+    }
+
+    #[test]
+    fn parse_rejects_invalid_shapes() {
+        for text in [
+            "[a]\nrect = 0.5, 0.1, 0.2, 0.4",
+            "[a]\nrect = 0.1, 0.4, 0.2, 0.1",
+            "[a]\nrect = -0.1, 0.1, 0.2, 0.4",
+            "[a]\nrect = 0.1, 0.1, 1.2, 0.4",
+            "[a]\nrect = 0.1, 0.1, 0.2",
+            "[a]\npolygon = 0.1 0.1, 0.5 0.1",
+            "[a]\npolygon = 0.1 0.1, 1.5 0.1, 0.3 0.3",
+            "[a]\ncolor = #e69f00",
+            "rect = 0.1, 0.1, 0.2, 0.4",
+            "[a]\nsize = 3",
+        ] {
+            assert!(Template::parse(text).is_err(), "{:?} was parsed", text);
+        }
+    }
+
+    #[test]
+    fn new_cuts_the_regions_to_the_image() {
+        let regions = [
+            Region::new(-20.0, 25.0, 50.0, 300.0, "partly outside"),
+            Region::new(300.0, 10.0, 350.0, 50.0, "outside"),
+        ];
+        let template = Template::new("cut", &regions, (200, 100));
+
+        assert_eq!(template.len(), 1);
+        assert_eq!(template.regions[0].state,
+            RegionState::Complete { x1: 0.0, y1: 0.25, x2: 0.25, y2: 1.0 });
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let dir = std::env::temp_dir()
+            .join(format!("plotview-template-{}", std::process::id()));
+        let path = dir.join("weekly.v2");
+        let page = Image::from_buffer(
+            RgbaImage::from_pixel(200, 100, Rgba([255, 255, 255, 255])),
+            "page.png"
+        );
+        let mut polygon = Region::polygon(
+            vec![(20.0, 20.0), (180.0, 20.0), (100.0, 90.0)], "Figure 2");
+        polygon.key_color = Some(Color32::from_rgb(10, 20, 30));
+        polygon.threshold = 90;
+        let regions = [
+            Region::new(20.0, 10.0, 100.0, 50.0, "Figure 1")
+                .with_color(Color32::from_rgb(0xe6, 0x9f, 0x00)),
+            polygon,
+        ];
+        let saved = Template::new("weekly.v2", &regions, page.dimensions())
+            .with_reference(&page);
+        saved.save(&path).unwrap();
+        let loaded = Template::load(&path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(loaded.has_reference());
+        assert_eq!(loaded.len(), saved.len());
+        for (loaded, saved) in loaded.regions.iter().zip(&saved.regions) {
+            assert_eq!(loaded.name, saved.name);
+            assert_eq!(loaded.color, saved.color);
+            assert_eq!(loaded.key_color, saved.key_color);
+            assert_eq!(loaded.threshold, saved.threshold);
+            assert_eq!(loaded.state, saved.state);
+        }
+    }
+
+    #[test]
+    fn names_with_a_slash_are_paths() {
+        assert_eq!(Template::path("./weekly"), Some(PathBuf::from("./weekly")));
+        assert_eq!(Template::find("./surely/not/a/template"), None);
+    }
+}
@@ -0,0 +1,102 @@
+use std::ops::DerefMut;
+use std::sync::OnceLock;
+
+use ab_glyph::{point, Font, FontVec, Glyph, PxScale, ScaleFont};
+use image::{ImageBuffer, Rgba};
+
+/// Font used for the text drawn outside of egui, the same proportional font
+/// egui uses by default so both look alike
+fn font() -> &'static FontVec {
+    static FONT: OnceLock<FontVec> = OnceLock::new();
+    FONT.get_or_init(|| {
+        let data = egui::FontDefinitions::default()
+            .font_data
+            .remove("Ubuntu-Light")
+            .expect("egui default fonts are missing");
+        FontVec::try_from_vec_and_index(data.font.into_owned(), data.index)
+            .expect("egui default font is invalid")
+    })
+}
+
+/// Position every glyph of a single line of `text`, with its top left corner
+/// at (x, y)
+///
+/// Returns the glyphs and the width of the line
+fn layout(text: &str, size: f32, x: f32, y: f32) -> (Vec<Glyph>, f32) {
+    let font = font().as_scaled(PxScale::from(size));
+    let mut caret = point(x, y + font.ascent());
+    let mut glyphs = Vec::new();
+    let mut last = None;
+    for c in text.chars() {
+        let id = font.glyph_id(c);
+        if let Some(last) = last {
+            caret.x += font.kern(last, id);
+        }
+        glyphs.push(id.with_scale_and_position(size, caret));
+        caret.x += font.h_advance(id);
+        last = Some(id);
+    }
+
+    (glyphs, caret.x - x)
+}
+
+/// Size (width, height) in pixels of a single line of `text`
+pub fn measure(text: &str, size: f32) -> (f32, f32) {
+    let (_, width) = layout(text, size, 0.0, 0.0);
+    (width, font().as_scaled(PxScale::from(size)).height())
+}
+
+/// Draw a single line of `text` with its top left corner at (x, y), blending
+/// `color` over the pixels of `image`
+pub fn draw<C>(
+    image: &mut ImageBuffer<Rgba<u8>, C>,
+    text: &str,
+    x: f32, y: f32,
+    size: f32,
+    color: Rgba<u8>
+) where C: DerefMut<Target = [u8]> {
+    let (width, height) = image.dimensions();
+    let (glyphs, _) = layout(text, size, x, y);
+    for glyph in glyphs {
+        let outlined = match font().outline_glyph(glyph) {
+            Some(outlined) => outlined,
+            None => continue,
+        };
+
+        let bounds = outlined.px_bounds();
+        outlined.draw(|gx, gy, coverage| {
+            let px = bounds.min.x as i64 + gx as i64;
+            let py = bounds.min.y as i64 + gy as i64;
+            if px < 0 || py < 0 || px >= width as i64 || py >= height as i64 {
+                return;
+            }
+
+            let pixel = image.get_pixel_mut(px as u32, py as u32);
+            let alpha = coverage.clamp(0.0, 1.0) * color[3] as f32 / 255.0;
+            for i in 0..3 {
+                pixel[i] = (color[i] as f32 * alpha
+                    + pixel[i] as f32 * (1.0 - alpha)).round() as u8;
+            }
+            pixel[3] = (alpha * 255.0
+                + pixel[3] as f32 * (1.0 - alpha)).round() as u8;
+        });
+    }
+}
+
+/// Draw a single line of `text` over a pixmap, like `draw`
+pub fn draw_on_pixmap(
+    pixmap: &mut tiny_skia::PixmapMut,
+    text: &str,
+    x: f32, y: f32,
+    size: f32,
+    color: Rgba<u8>
+) {
+    let (width, height) = (pixmap.width(), pixmap.height());
+    if let Some(mut canvas) = ImageBuffer::<Rgba<u8>, _>::from_raw(
+        width,
+        height,
+        pixmap.data_mut()
+    ) {
+        draw(&mut canvas, text, x, y, size, color);
+    }
+}
@@ -0,0 +1,112 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+/// Time between checks of the watched file
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Watches a file for changes by polling its modification time and size,
+/// so it works on any platform and file system
+pub struct Watcher {
+    path: PathBuf,
+
+    /// Modification time and size last seen, `None` if the file is missing
+    seen: Option<(SystemTime, u64)>,
+}
+
+/// Modification time and size of the file on `path`
+fn stamp(path: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = fs::metadata(path).ok()?;
+
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+impl Watcher {
+    /// Watch the file on `path`, its current contents don't count as a change
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let seen = stamp(&path);
+
+        Self {
+            path,
+            seen
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// If the file changed since the last call, a file being written is only
+    /// reported once it stops changing and a removed one isn't reported
+    /// until it's back
+    pub fn changed(&mut self) -> bool {
+        let mut current = stamp(&self.path);
+        if current == self.seen {
+            return false;
+        }
+
+        // Wait for whoever is writing the file to finish
+        loop {
+            thread::sleep(POLL_INTERVAL);
+            let again = stamp(&self.path);
+            if again == current {
+                break;
+            }
+            current = again;
+        }
+        self.seen = current;
+
+        current.is_some()
+    }
+
+    /// Block till the file changes
+    pub fn wait(&mut self) {
+        self.wait_while(&AtomicBool::new(true));
+    }
+
+    /// Block till the file changes or `running` is cleared
+    ///
+    /// Returns if the file changed, `false` once `running` is cleared.
+    pub fn wait_while(&mut self, running: &AtomicBool) -> bool {
+        while running.load(Ordering::Relaxed) {
+            if self.changed() {
+                return running.load(Ordering::Relaxed);
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[test]
+    fn wait_while_reports_changes_and_stops() {
+        let path = std::env::temp_dir()
+            .join(format!("plotview-watch-{}", std::process::id()));
+        fs::write(&path, "first").unwrap();
+        let mut watcher = Watcher::new(&path);
+        let running = Arc::new(AtomicBool::new(true));
+
+        fs::write(&path, "second version").unwrap();
+        assert!(watcher.wait_while(&running));
+
+        let stop = Arc::clone(&running);
+        let stopper = thread::spawn(move || {
+            thread::sleep(POLL_INTERVAL * 2);
+            stop.store(false, Ordering::Relaxed);
+        });
+        assert!(!watcher.wait_while(&running));
+        stopper.join().unwrap();
+
+        fs::remove_file(&path).unwrap();
+    }
+}